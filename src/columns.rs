@@ -0,0 +1,66 @@
+use crate::inputfile::ColumnFilter;
+use crate::table_config::glob;
+use regex::Regex;
+use std::error::Error;
+
+/// The conditions from `filters` that apply to a table with `columns`.
+pub fn filter_conditions(filters: &[ColumnFilter], columns: &[String]) -> Vec<String> {
+    filters
+        .iter()
+        .filter(|filter| columns.contains(&filter.column))
+        .map(|filter| filter.where_clause.clone())
+        .collect()
+}
+
+/// Columns to leave out of the dump, from `exclude_columns`.
+///
+/// Each pattern is `table.column`, where either part may use `*` to match
+/// any run of characters, as in `*.search_vector`.
+#[derive(Debug, Default)]
+pub struct ColumnExclusions {
+    patterns: Vec<(Regex, Regex)>,
+}
+
+impl ColumnExclusions {
+    pub fn new(patterns: Vec<String>) -> Result<ColumnExclusions, Box<dyn Error>> {
+        let patterns = patterns
+            .iter()
+            .map(|pattern| {
+                let (table, column) = pattern.split_once('.').ok_or_else(|| {
+                    format!("exclude_columns: expected table.column, not {pattern:?}")
+                })?;
+                Ok((glob(table)?, glob(column)?))
+            })
+            .collect::<Result<_, Box<dyn Error>>>()?;
+        Ok(ColumnExclusions { patterns })
+    }
+
+    /// Whether to leave `column` out of the table called `table`, or out of
+    /// partitions of any of its `ancestors`.
+    pub fn is_excluded(&self, table: &str, ancestors: &[String], column: &str) -> bool {
+        self.patterns.iter().any(|(table_pattern, column_pattern)| {
+            column_pattern.is_match(column)
+                && std::iter::once(table)
+                    .chain(ancestors.iter().map(String::as_str))
+                    .any(|table| table_pattern.is_match(table))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::columns::*;
+
+    #[test]
+    fn it_matches_table_and_column_globs() {
+        let exclusions = ColumnExclusions::new(vec![
+            "documents.body".to_owned(),
+            "*.search_vector".to_owned(),
+        ])
+        .unwrap();
+        assert!(exclusions.is_excluded("documents", &[], "body"));
+        assert!(!exclusions.is_excluded("documents_archive", &[], "body"));
+        assert!(exclusions.is_excluded("notes", &[], "search_vector"));
+        assert!(exclusions.is_excluded("documents_2024", &["documents".to_owned()], "body"));
+    }
+}
@@ -0,0 +1,290 @@
+use crate::manifest::Manifest;
+use crate::parcel::{split_row, CopyBlock, Parcel};
+use clap::ValueEnum;
+use postgres::Client;
+use serde_derive::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::io::{BufRead, BufReader};
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum DiffFormat {
+    Text,
+    Json,
+}
+
+/// A row as column name to value. `None` is SQL `NULL`.
+type Row = BTreeMap<String, Option<String>>;
+
+#[derive(Serialize, Debug)]
+pub struct TableDiff {
+    pub table: String,
+    /// The columns rows were matched on: the primary key if there is one,
+    /// otherwise every column.
+    pub key: Vec<String>,
+    pub inserted: Vec<Row>,
+    pub deleted: Vec<Row>,
+    pub changed: Vec<ChangedRow>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ChangedRow {
+    pub key: Row,
+    pub changes: BTreeMap<String, Change>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Change {
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+impl TableDiff {
+    fn is_empty(&self) -> bool {
+        self.inserted.is_empty() && self.deleted.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Build a parcel from the live database by re-running each query recorded in
+/// `manifest`. Column names are taken from the matching blocks in `parcel`.
+pub fn read_live(
+    client: &mut Client,
+    manifest: &Manifest,
+    parcel: &Parcel,
+) -> Result<Parcel, Box<dyn Error>> {
+    let mut blocks = Vec::with_capacity(manifest.tables.len());
+    for table in manifest.tables.iter() {
+        let Some(block) = parcel.blocks.iter().find(|block| block.name == table.name) else {
+            eprintln!("Skipping {}: not found in parcel", table.name);
+            continue;
+        };
+        // Read as bytes, as `Parcel::read` does, since `bytea` and escaped
+        // text needn't be valid UTF-8.
+        let reader = client.copy_out(&format!("COPY ({}) TO stdout;", table.query))?;
+        let mut lines = Vec::new();
+        let mut bytes = 0;
+        for line in BufReader::new(reader).split(b'\n') {
+            let line = line?;
+            bytes += line.len() as u64 + 1;
+            lines.push(String::from_utf8_lossy(&line).into_owned());
+        }
+        blocks.push(CopyBlock {
+            name: table.name.clone(),
+            columns: block.columns.clone(),
+            lines,
+            rows: 0,
+            bytes,
+            sha256: String::new(),
+            complete: true,
+        });
+    }
+    Ok(Parcel {
+        manifest: Some(manifest.clone()),
+        blocks,
+    })
+}
+
+/// Compare every table in `old` and `new`, matching rows by primary key.
+pub fn diff(old: &Parcel, new: &Parcel) -> Vec<TableDiff> {
+    let mut names: Vec<&String> = old
+        .blocks
+        .iter()
+        .chain(new.blocks.iter())
+        .map(|block| &block.name)
+        .collect();
+    names.sort();
+    names.dedup();
+
+    names
+        .into_iter()
+        .map(|name| {
+            let old_block = old.blocks.iter().find(|block| &block.name == name);
+            let new_block = new.blocks.iter().find(|block| &block.name == name);
+            let primary_key = primary_key(old, name).or_else(|| primary_key(new, name));
+            diff_table(name, primary_key.unwrap_or_default(), old_block, new_block)
+        })
+        .filter(|diff| !diff.is_empty())
+        .collect()
+}
+
+fn primary_key(parcel: &Parcel, name: &str) -> Option<Vec<String>> {
+    parcel
+        .manifest
+        .as_ref()?
+        .tables
+        .iter()
+        .find(|table| table.name == name)
+        .map(|table| table.primary_key.clone())
+        .filter(|primary_key| !primary_key.is_empty())
+}
+
+fn rows(block: &CopyBlock) -> Vec<Row> {
+    block
+        .lines
+        .iter()
+        .map(|line| block.columns.iter().cloned().zip(split_row(line)).collect())
+        .collect()
+}
+
+fn diff_table(
+    name: &str,
+    primary_key: Vec<String>,
+    old: Option<&CopyBlock>,
+    new: Option<&CopyBlock>,
+) -> TableDiff {
+    let old_rows = old.map(rows).unwrap_or_default();
+    let new_rows = new.map(rows).unwrap_or_default();
+
+    // Only columns present on both sides can be compared. A table missing from
+    // one side has no rows there, so its columns don't matter.
+    let old_columns = old.or(new).map(|block| &block.columns);
+    let new_columns = new.or(old).map(|block| &block.columns);
+    let common: Vec<String> = old_columns
+        .into_iter()
+        .flatten()
+        .filter(|column| new_columns.into_iter().flatten().any(|c| c == *column))
+        .cloned()
+        .collect();
+    let key = if !primary_key.is_empty() && primary_key.iter().all(|c| common.contains(c)) {
+        primary_key
+    } else {
+        common.clone()
+    };
+    let key_of = |row: &Row| -> Vec<Option<String>> {
+        key.iter()
+            .map(|column| row.get(column).cloned().flatten())
+            .collect()
+    };
+
+    let mut unmatched: HashMap<Vec<Option<String>>, Vec<usize>> = HashMap::new();
+    for (index, row) in old_rows.iter().enumerate().rev() {
+        unmatched.entry(key_of(row)).or_default().push(index);
+    }
+    let mut matched = vec![false; old_rows.len()];
+
+    let mut diff = TableDiff {
+        table: name.to_owned(),
+        key: key.clone(),
+        inserted: Vec::new(),
+        deleted: Vec::new(),
+        changed: Vec::new(),
+    };
+    for new_row in new_rows.into_iter() {
+        match unmatched.get_mut(&key_of(&new_row)).and_then(Vec::pop) {
+            Some(index) => {
+                matched[index] = true;
+                let old_row = &old_rows[index];
+                let changes: BTreeMap<String, Change> = common
+                    .iter()
+                    .filter(|column| old_row.get(*column) != new_row.get(*column))
+                    .map(|column| {
+                        let change = Change {
+                            old: old_row.get(column).cloned().flatten(),
+                            new: new_row.get(column).cloned().flatten(),
+                        };
+                        (column.clone(), change)
+                    })
+                    .collect();
+                if !changes.is_empty() {
+                    let key = key
+                        .iter()
+                        .map(|column| (column.clone(), new_row.get(column).cloned().flatten()))
+                        .collect();
+                    diff.changed.push(ChangedRow { key, changes });
+                }
+            }
+            None => diff.inserted.push(new_row),
+        }
+    }
+    diff.deleted = old_rows
+        .into_iter()
+        .zip(matched)
+        .filter(|(_, matched)| !matched)
+        .map(|(row, _)| row)
+        .collect();
+    diff
+}
+
+fn display_value(value: &Option<String>) -> String {
+    match value {
+        Some(value) => format!("{value:?}"),
+        None => "NULL".to_owned(),
+    }
+}
+
+fn display_key(key: &[String], row: &Row) -> String {
+    key.iter()
+        .map(|column| {
+            let value = row.get(column).cloned().flatten();
+            format!("{column}={}", display_value(&value))
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+pub fn report(diffs: &[TableDiff], format: DiffFormat) -> Result<(), Box<dyn Error>> {
+    match format {
+        DiffFormat::Json => println!("{}", serde_json::to_string_pretty(diffs)?),
+        DiffFormat::Text => {
+            if diffs.is_empty() {
+                println!("No differences");
+            }
+            for diff in diffs.iter() {
+                println!(
+                    "{}: {} inserted, {} deleted, {} changed",
+                    diff.table,
+                    diff.inserted.len(),
+                    diff.deleted.len(),
+                    diff.changed.len()
+                );
+                for row in diff.inserted.iter() {
+                    println!("  + {}", display_key(&diff.key, row));
+                }
+                for row in diff.deleted.iter() {
+                    println!("  - {}", display_key(&diff.key, row));
+                }
+                for row in diff.changed.iter() {
+                    println!("  ~ {}", display_key(&diff.key, &row.key));
+                    for (column, change) in row.changes.iter() {
+                        println!(
+                            "      {column}: {} -> {}",
+                            display_value(&change.old),
+                            display_value(&change.new)
+                        );
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diff::*;
+
+    fn block(lines: &[&str]) -> CopyBlock {
+        CopyBlock {
+            name: "users".to_owned(),
+            columns: vec!["id".to_owned(), "email".to_owned()],
+            lines: lines.iter().map(|line| line.to_string()).collect(),
+            rows: lines.len() as u64,
+            bytes: 0,
+            sha256: String::new(),
+            complete: true,
+        }
+    }
+
+    #[test]
+    fn it_matches_rows_by_primary_key() {
+        let old = block(&["1\ta@example.com", "2\tb@example.com", "3\t\\N"]);
+        let new = block(&["1\ta@example.com", "3\tc@example.com", "4\td@example.com"]);
+        let diff = diff_table("users", vec!["id".to_owned()], Some(&old), Some(&new));
+        assert_eq!(diff.inserted.len(), 1);
+        assert_eq!(diff.inserted[0]["id"].as_deref(), Some("4"));
+        assert_eq!(diff.deleted.len(), 1);
+        assert_eq!(diff.deleted[0]["id"].as_deref(), Some("2"));
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].changes["email"].old, None);
+    }
+}
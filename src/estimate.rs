@@ -0,0 +1,170 @@
+use crate::{Options, Table};
+use indicatif::ProgressBar;
+use postgres::error::SqlState;
+use postgres::Client;
+use std::error::Error;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Number of rows a table's `copy_out_query` selects.
+#[derive(Debug, Clone, Copy)]
+pub enum RowCount {
+    /// Counted with `SELECT COUNT(*)`.
+    Exact(u64),
+    /// Taken from the query planner's estimate.
+    Approximate(u64),
+}
+
+impl RowCount {
+    pub fn value(&self) -> u64 {
+        match self {
+            RowCount::Exact(rows) | RowCount::Approximate(rows) => *rows,
+        }
+    }
+
+    pub fn is_approximate(&self) -> bool {
+        matches!(self, RowCount::Approximate(_))
+    }
+}
+
+impl fmt::Display for RowCount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RowCount::Exact(rows) => write!(f, "{rows}"),
+            RowCount::Approximate(rows) => write!(f, "~{rows}"),
+        }
+    }
+}
+
+fn count_statement(query: &str) -> String {
+    // The `postgres` crate does not define `FromSql for u64` (or usize, or
+    // u128), so it would appear that the only safe way to query a PostgreSQL
+    // `int8` is as text.
+    format!("SELECT COUNT(*)::text FROM ({query}) AS query")
+}
+
+/// Count the rows selected by `query`.
+pub fn exact_row_count(client: &mut Client, query: &str) -> Result<u64, Box<dyn Error>> {
+    let row_count_s: String = client.query_one(&count_statement(query), &[])?.get(0);
+    Ok(row_count_s.parse()?)
+}
+
+/// Count the rows selected by `query`, giving up after `timeout`.
+///
+/// The count runs inside a savepoint so that a cancelled statement does not
+/// abort the surrounding transaction.
+fn exact_row_count_within(
+    client: &mut Client,
+    query: &str,
+    timeout: Duration,
+) -> Result<Option<u64>, Box<dyn Error>> {
+    client.batch_execute(&format!(
+        "SAVEPOINT pg_parcel_count; SET LOCAL statement_timeout = {}",
+        timeout.as_millis().max(1)
+    ))?;
+    let row_count = match client.query_one(&count_statement(query), &[]) {
+        Ok(row) => Some(row.get::<_, String>(0).parse()?),
+        Err(err) if err.code() == Some(&SqlState::QUERY_CANCELED) => None,
+        Err(err) => return Err(err.into()),
+    };
+    // Rolling back also discards the `SET LOCAL`.
+    client.batch_execute(
+        "ROLLBACK TO SAVEPOINT pg_parcel_count; RELEASE SAVEPOINT pg_parcel_count",
+    )?;
+    Ok(row_count)
+}
+
+/// Ask the query planner how many rows `query` will return.
+pub fn planner_row_count(client: &mut Client, query: &str) -> Result<u64, Box<dyn Error>> {
+    let plan: serde_json::Value = client
+        .query_one(&format!("EXPLAIN (FORMAT JSON) {query}"), &[])?
+        .get(0);
+    let rows = plan[0]["Plan"]["Plan Rows"].as_f64().unwrap_or(0.0);
+    Ok(rows.max(0.0) as u64)
+}
+
+/// Row counts for `--fast-estimate`, in the same order as `tables`.
+///
+/// Every table starts with the planner's estimate. If there is an exact count
+/// budget, tables are then counted exactly, smallest estimate first, until the
+/// budget runs out.
+pub fn fast_row_counts(
+    client: &mut Client,
+    tables: &[Table],
+    options: &Options,
+    pb: &ProgressBar,
+) -> Result<Vec<RowCount>, Box<dyn Error>> {
+    let mut row_counts = Vec::with_capacity(tables.len());
+    for table in tables.iter() {
+        pb.set_message(table.name.to_owned());
+        let rows = planner_row_count(client, &table.copy_out_query(options))?;
+        row_counts.push(RowCount::Approximate(rows));
+    }
+
+    if let Some(budget) = options.exact_count_budget {
+        count_exactly(&mut row_counts, budget, |index, remaining| {
+            let table = &tables[index];
+            pb.set_message(table.name.to_owned());
+            exact_row_count_within(client, &table.copy_out_query(options), remaining)
+        })?;
+    }
+
+    Ok(row_counts)
+}
+
+/// Replace planner estimates in `row_counts` with exact counts from `count`,
+/// smallest estimate first, until `budget` runs out or a count times out.
+/// `count` is given a table's index and the time left.
+fn count_exactly(
+    row_counts: &mut [RowCount],
+    budget: Duration,
+    mut count: impl FnMut(usize, Duration) -> Result<Option<u64>, Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    let started = Instant::now();
+    let mut smallest_first: Vec<usize> = (0..row_counts.len()).collect();
+    smallest_first.sort_by_key(|&index| row_counts[index].value());
+    for index in smallest_first {
+        let remaining = budget.saturating_sub(started.elapsed());
+        if remaining.is_zero() {
+            break;
+        }
+        match count(index, remaining)? {
+            Some(rows) => row_counts[index] = RowCount::Exact(rows),
+            None => break,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::estimate::*;
+
+    #[test]
+    fn it_counts_smallest_tables_first_until_a_count_times_out() {
+        let mut row_counts = vec![
+            RowCount::Approximate(500),
+            RowCount::Approximate(10),
+            RowCount::Approximate(100),
+        ];
+        let mut counted = Vec::new();
+        count_exactly(&mut row_counts, Duration::from_secs(60), |index, _| {
+            counted.push(index);
+            // The second table takes too long.
+            Ok((counted.len() == 1).then_some(12))
+        })
+        .unwrap();
+        assert_eq!(counted, [1, 2]);
+        assert_eq!(
+            row_counts.iter().map(|c| c.to_string()).collect::<Vec<_>>(),
+            ["~500", "12", "~100"]
+        );
+    }
+
+    #[test]
+    fn it_keeps_planner_estimates_without_a_budget() {
+        let mut row_counts = vec![RowCount::Approximate(10)];
+        count_exactly(&mut row_counts, Duration::ZERO, |_, _| Ok(Some(10))).unwrap();
+        assert!(row_counts[0].is_approximate());
+    }
+}
@@ -0,0 +1,151 @@
+use crate::sql_string::SqlString;
+use crate::suggestion::did_you_mean;
+use crate::{substitute_placeholders, tables_query, Options, Table};
+use postgres::Client;
+use std::error::Error;
+
+/// Where to find every valid tenant ID, from `root_table` or `id_query`.
+#[derive(Debug, Clone)]
+pub enum IdCheck {
+    /// A table whose single-column primary key holds the IDs.
+    RootTable(String),
+    /// A query whose first column holds the IDs.
+    Query(String),
+}
+
+impl IdCheck {
+    pub fn new(
+        root_table: Option<String>,
+        id_query: Option<String>,
+    ) -> Result<Option<IdCheck>, Box<dyn Error>> {
+        match (root_table, id_query) {
+            (Some(_), Some(_)) => Err("Set root_table or id_query, not both".into()),
+            (Some(table), None) => Ok(Some(IdCheck::RootTable(table))),
+            (None, Some(query)) => Ok(Some(IdCheck::Query(query))),
+            (None, None) => Ok(None),
+        }
+    }
+}
+
+/// Make sure every requested ID exists, as of the snapshot `client` is in.
+/// Missing IDs are reported with the closest existing ID, if there is one;
+/// only then are all of the IDs read.
+pub fn check_ids(
+    client: &mut Client,
+    options: &Options,
+    check: &IdCheck,
+    tables: &[Table],
+) -> Result<(), Box<dyn Error>> {
+    let (source, column, query) = match check {
+        IdCheck::RootTable(table) => {
+            let primary_key = root_primary_key(client, options, table, tables)?;
+            let [column] = primary_key.as_slice() else {
+                return Err(format!(
+                    "root_table {table} needs a primary key of one column to check IDs against"
+                )
+                .into());
+            };
+            (
+                table.clone(),
+                column.sql_identifier(),
+                format!(
+                    "SELECT {} FROM {}.{}",
+                    column.sql_identifier(),
+                    options.schema.sql_identifier(),
+                    table.sql_identifier()
+                ),
+            )
+        }
+        IdCheck::Query(query) => (
+            "id_query".to_owned(),
+            "ids".to_owned(),
+            format!(
+                "SELECT ids FROM ({}) AS ids (ids)",
+                substitute_placeholders(query, options)
+            ),
+        ),
+    };
+    let found: Vec<bool> = client
+        .query_one(&exists_query(&column, &query, &options.column_values), &[])?
+        .get(0);
+    let missing: Vec<&String> = options
+        .column_values
+        .iter()
+        .zip(found)
+        .filter(|(_, found)| !found)
+        .map(|(id, _)| id)
+        .collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+    // Only now read every ID, to suggest the closest ones.
+    let existing: Vec<String> = client
+        .query(
+            &format!("SELECT {column}::text FROM ({query}) AS existing ({column})"),
+            &[],
+        )?
+        .into_iter()
+        .filter_map(|row| row.get(0))
+        .collect();
+    let missing: Vec<String> = missing
+        .into_iter()
+        .map(|id| format!("No ID `{id}` in {source}.{}", did_you_mean(&existing, id)))
+        .collect();
+    Err(missing.join(" ").into())
+}
+
+/// The primary key of `root_table`, from `tables` if it is dumped, or else
+/// from the catalog query they were read with.
+fn root_primary_key(
+    client: &mut Client,
+    options: &Options,
+    root_table: &str,
+    tables: &[Table],
+) -> Result<Vec<String>, Box<dyn Error>> {
+    if let Some(table) = tables.iter().find(|table| table.name == root_table) {
+        return Ok(table.primary_key.clone());
+    }
+    let query = tables_query(client, options)?;
+    Ok(client
+        .query(&query, &[])?
+        .into_iter()
+        .find(|row| row.get::<_, String>("table_name") == root_table)
+        .map(|row| row.get("primary_key"))
+        .unwrap_or_default())
+}
+
+/// A query for whether each of `ids` is in `column` of `query`. The IDs are
+/// literals, as in the dump's scope condition, so they take the column's type
+/// and can be looked up by its index.
+fn exists_query(column: &str, query: &str, ids: &[String]) -> String {
+    let exists: Vec<String> = ids
+        .iter()
+        .map(|id| {
+            format!(
+                "EXISTS (SELECT FROM ({query}) AS existing ({column}) WHERE {column} = {})",
+                id.sql_value()
+            )
+        })
+        .collect();
+    format!("SELECT ARRAY[{}]::bool[]", exists.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::id_check::*;
+
+    #[test]
+    fn it_compares_ids_as_literals_of_the_column_type() {
+        assert_eq!(
+            exists_query(
+                "\"id\"",
+                "SELECT \"id\" FROM \"public\".customers",
+                &["acme".to_owned(), "007".to_owned()]
+            ),
+            "SELECT ARRAY[\
+             EXISTS (SELECT FROM (SELECT \"id\" FROM \"public\".customers) AS existing (\"id\") WHERE \"id\" = 'acme'), \
+             EXISTS (SELECT FROM (SELECT \"id\" FROM \"public\".customers) AS existing (\"id\") WHERE \"id\" = '007')\
+             ]::bool[]"
+        );
+    }
+}
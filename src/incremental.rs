@@ -0,0 +1,288 @@
+use crate::inputfile::IncrementalConfig;
+use crate::manifest::{Manifest, TableManifest};
+use crate::parcel::Parcel;
+use crate::sql_string::SqlString;
+use crate::{write_copy_block, write_table, Options, Table};
+use postgres::Client;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+/// A row's `xmin` as an `xid8`. `xmin` is only 32 bits, so this takes the
+/// latest transaction ID with the same low bits that the current snapshot
+/// can see. Live rows are never that far behind, and frozen rows that come
+/// out too recent are only sent again.
+const XMIN_XID8: &str = "(pg_snapshot_xmax(pg_current_snapshot())::text::int8 - ((pg_snapshot_xmax(pg_current_snapshot())::text::int8 - xmin::text::int8) % 4294967296 + 4294967296) % 4294967296)::text::xid8";
+
+/// Change column that finds rows by the transaction that last wrote them,
+/// rather than by a timestamp.
+const XMIN: &str = "xmin";
+
+/// Settings for a delta parcel, made with `--incremental-from`.
+#[derive(Debug)]
+pub struct Incremental {
+    /// The previous parcel's watermark. Rows whose change column is at or
+    /// after this time are dumped.
+    pub since: String,
+    /// The previous parcel's snapshot. With `xmin`, rows written by
+    /// transactions it can't see are dumped.
+    pg_snapshot: Option<String>,
+    column: Option<String>,
+    tables: HashMap<String, String>,
+}
+
+impl Incremental {
+    /// Read the previous run's manifest from `path`, which may be either a
+    /// sidecar manifest or a parcel with one embedded.
+    pub fn load(
+        path: &Path,
+        config: IncrementalConfig,
+    ) -> Result<(Incremental, Manifest), Box<dyn Error>> {
+        let previous = match toml::from_str::<Manifest>(&fs::read_to_string(path)?) {
+            Ok(manifest) => manifest,
+            Err(_) => Parcel::load(path, false)?
+                .manifest
+                .ok_or_else(|| format!("No manifest found in {}", path.display()))?,
+        };
+        if previous.snapshot.is_empty() {
+            return Err(format!("No snapshot time recorded in {}", path.display()).into());
+        }
+        let tables = config.tables.unwrap_or_default();
+        let uses_xmin = config
+            .column
+            .iter()
+            .chain(tables.values())
+            .any(|c| c == XMIN);
+        if uses_xmin && previous.pg_snapshot.is_none() {
+            return Err(format!(
+                "No pg_snapshot recorded in {}, which xmin needs. It was made by an older pg_parcel, or from PostgreSQL 12 or earlier.",
+                path.display()
+            )
+            .into());
+        }
+        let incremental = Incremental {
+            // Parcels from before the watermark was recorded.
+            since: previous
+                .watermark
+                .clone()
+                .unwrap_or_else(|| previous.snapshot.clone()),
+            pg_snapshot: previous.pg_snapshot.clone(),
+            column: config.column,
+            tables,
+        };
+        Ok((incremental, previous))
+    }
+
+    /// The column used to find changed rows in `table`, if there is one.
+    fn change_column(&self, table: &Table) -> Option<&String> {
+        self.tables.get(&table.name).or_else(|| {
+            self.column.as_ref().filter(|column| {
+                *column == XMIN || table.columns.iter().any(|c| &c.name == *column)
+            })
+        })
+    }
+
+    /// The condition picking changed rows out of `table`'s scoped query, or
+    /// `None` if every row should be sent.
+    fn changed_rows(&self, table: &Table, primary_key: &str) -> Option<String> {
+        let column = self.change_column(table)?;
+        if column == XMIN {
+            // The scoped query may not have `xmin`, so match keys against the
+            // table's own rows.
+            return Some(format!(
+                "({primary_key}) IN (SELECT {primary_key} FROM {} WHERE NOT pg_visible_in_snapshot({}, {}::pg_snapshot))",
+                table.sql_identifier(),
+                XMIN_XID8,
+                self.pg_snapshot
+                    .clone()
+                    .unwrap_or_default()
+                    .replace('\'', "''")
+                    .sql_value()
+            ));
+        }
+        Some(format!(
+            "{} >= {}::timestamptz",
+            column.sql_identifier(),
+            self.since.replace('\'', "''").sql_value()
+        ))
+    }
+
+    /// Write the statements that bring `table` up to date.
+    ///
+    /// Tables with a primary key get an upsert of the changed rows, followed
+    /// by a delete of rows whose keys are no longer in scope. The current keys
+    /// are shipped in a second `COPY` block, named after the table with a
+    /// `.keys` suffix. Tables without a primary key are deleted and dumped in
+    /// full.
+    pub fn dump_table(
+        &self,
+        client: &mut Client,
+        table: &Table,
+        options: &Options,
+    ) -> Result<Vec<TableManifest>, Box<dyn Error>> {
+        let mut stdout = std::io::stdout();
+        let target = table.sql_identifier();
+        let scope = table.scope_condition(options);
+
+        if table.primary_key.is_empty() {
+            match &scope {
+                Some(condition) => writeln!(stdout, "DELETE FROM {target} WHERE {condition};")?,
+                None => writeln!(stdout, "DELETE FROM {target};")?,
+            }
+            return Ok(vec![write_table(client, table, options)?]);
+        }
+
+        let columns = table.column_list();
+        let primary_key = table
+            .primary_key
+            .iter()
+            .map(|column| column.sql_identifier())
+            .collect::<Vec<String>>()
+            .join(", ");
+        let scoped = format!("({}) AS scoped", table.copy_out_query(options));
+
+        // Changed rows go into a temporary table of the same name, and from
+        // there into the real one.
+        let changes = format!("pg_temp.{}", table.name.sql_identifier());
+        let changes_query = match self.changed_rows(table, &primary_key) {
+            Some(condition) => format!("SELECT {columns} FROM {scoped} WHERE {condition}"),
+            None => format!("SELECT {columns} FROM {scoped}"),
+        };
+        writeln!(
+            stdout,
+            "CREATE TEMPORARY TABLE {changes} AS SELECT {columns} FROM {target} WITH NO DATA;"
+        )?;
+        let changes_entry = write_copy_block(
+            client,
+            options,
+            &format!("COPY {changes} ({columns}) FROM stdin"),
+            table,
+            &table.name,
+            &table.column_names(),
+            changes_query,
+        )?;
+        let updates = table
+            .columns
+            .iter()
+            .filter(|column| !table.primary_key.contains(&column.name))
+            .map(|column| {
+                let column = column.name.sql_identifier();
+                format!("{column} = EXCLUDED.{column}")
+            })
+            .collect::<Vec<String>>();
+        let on_conflict = if updates.is_empty() {
+            "DO NOTHING".to_owned()
+        } else {
+            format!("DO UPDATE SET {}", updates.join(", "))
+        };
+        writeln!(
+            stdout,
+            "INSERT INTO {target} ({columns}) OVERRIDING SYSTEM VALUE SELECT {} FROM {changes} ON CONFLICT ({primary_key}) {on_conflict};",
+            table.select_list(options)
+        )?;
+
+        // Then every key still in scope, so that rows which have disappeared
+        // can be deleted.
+        let keys_name = format!("{}.keys", table.name);
+        let keys = format!("pg_temp.{}", keys_name.sql_identifier());
+        writeln!(
+            stdout,
+            "CREATE TEMPORARY TABLE {keys} AS SELECT {primary_key} FROM {target} WITH NO DATA;"
+        )?;
+        let keys_entry = write_copy_block(
+            client,
+            options,
+            &format!("COPY {keys} ({primary_key}) FROM stdin"),
+            table,
+            &keys_name,
+            &table.primary_key,
+            format!("SELECT {primary_key} FROM {scoped}"),
+        )?;
+        writeln!(stdout, "{}", delete_missing(table, &keys, scope))?;
+        writeln!(stdout, "DROP TABLE {changes}, {keys};")?;
+
+        Ok(vec![changes_entry, keys_entry])
+    }
+}
+
+/// The statement deleting rows of `table` in scope whose keys aren't in the
+/// `keys` temporary table.
+fn delete_missing(table: &Table, keys: &str, scope: Option<String>) -> String {
+    let target = table.sql_identifier();
+    let matches_key = table
+        .primary_key
+        .iter()
+        .map(|column| {
+            let column = column.sql_identifier();
+            format!("keys.{column} = {target}.{column}")
+        })
+        .collect::<Vec<String>>()
+        .join(" AND ");
+    format!(
+        "DELETE FROM {target} WHERE NOT EXISTS (SELECT FROM {keys} AS keys WHERE {matches_key}){};",
+        scope
+            .map(|condition| format!(" AND ({condition})"))
+            .unwrap_or_default()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::incremental::*;
+    use crate::Column;
+
+    fn users() -> Table {
+        Table {
+            name: "users".to_owned(),
+            schema: "public".to_owned(),
+            columns: vec![Column {
+                name: "updated_at".to_owned(),
+                is_nullable: true,
+                is_oid: false,
+            }],
+            primary_key: vec!["id".to_owned()],
+            ..Table::default()
+        }
+    }
+
+    #[test]
+    fn it_escapes_the_watermark() {
+        let incremental = Incremental {
+            since: "2024-01-01 00:00:00+00' OR true --".to_owned(),
+            pg_snapshot: None,
+            column: Some("updated_at".to_owned()),
+            tables: HashMap::new(),
+        };
+        assert_eq!(
+            incremental.changed_rows(&users(), "\"id\""),
+            Some(
+                r#""updated_at" >= '2024-01-01 00:00:00+00'' OR true --'::timestamptz"#.to_owned()
+            )
+        );
+    }
+
+    #[test]
+    fn it_sends_every_row_without_a_change_column() {
+        let incremental = Incremental {
+            since: "2024-01-01 00:00:00+00".to_owned(),
+            pg_snapshot: None,
+            column: Some("modified_at".to_owned()),
+            tables: HashMap::new(),
+        };
+        assert_eq!(incremental.changed_rows(&users(), "\"id\""), None);
+    }
+
+    #[test]
+    fn it_deletes_rows_missing_from_the_keys_in_scope() {
+        assert_eq!(
+            delete_missing(
+                &users(),
+                r#"pg_temp."users.keys""#,
+                Some("customer_id IN ('acme') OR customer_id IS NULL".to_owned())
+            ),
+            r#"DELETE FROM "public".users WHERE NOT EXISTS (SELECT FROM pg_temp."users.keys" AS keys WHERE keys."id" = "public".users."id") AND (customer_id IN ('acme') OR customer_id IS NULL);"#
+        );
+    }
+}
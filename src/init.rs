@@ -0,0 +1,298 @@
+use crate::sampling::{self, ForeignKey};
+use crate::sql_string::SqlString;
+use postgres::Client;
+use std::collections::{BTreeMap, VecDeque};
+use std::error::Error;
+use std::fmt::Write;
+
+/// How a table with no `column_name` column is tied to the tenant.
+#[derive(Debug, PartialEq)]
+enum Scope {
+    /// Referred to by `column_name` in other tables, through this column.
+    Tenant(String),
+    /// Reaches a table with `column_name` through these foreign keys, from
+    /// the table itself outwards.
+    Joined(Vec<usize>),
+    /// Neither.
+    Unreachable,
+}
+
+/// Write a starter config for `schema`, scoping tables by `column_name`.
+///
+/// Tables with the column are scoped by it without any configuration. Tables
+/// it refers to get an override selecting the requested IDs; tables that
+/// reach a scoped table through foreign keys get an override joining along
+/// the shortest path. Everything else is listed for the user to decide on.
+pub fn write_config(
+    client: &mut Client,
+    out: &mut impl std::io::Write,
+    schema: &str,
+    column_name: &str,
+) -> Result<(), Box<dyn Error>> {
+    let query = format!(
+        r#"
+        select
+          pg_class.relname::text as table_name,
+          array(
+            select attname::text from pg_attribute
+            where attrelid = pg_class.oid and attnum > 0 and not attisdropped
+            order by attnum
+          ) as column_names,
+          -- Generated columns can't be loaded, so they aren't dumped.
+          array(
+            select attname::text from pg_attribute
+            where attrelid = pg_class.oid and attnum > 0 and not attisdropped
+            and attgenerated = ''
+            order by attnum
+          ) as dumped_columns
+        from pg_class
+        join pg_namespace on pg_namespace.oid = pg_class.relnamespace
+        where pg_namespace.nspname = {schema}
+        and pg_class.relkind in ('r', 'p')
+        and not pg_class.relispartition
+        order by pg_class.relname
+        "#,
+        schema = schema.to_owned().sql_value(),
+    );
+    let rows = client.query(&query, &[])?;
+    let tables: BTreeMap<String, Vec<String>> = rows
+        .iter()
+        .map(|row| (row.get("table_name"), row.get("column_names")))
+        .collect();
+    let dumped_columns: BTreeMap<String, Vec<String>> = rows
+        .iter()
+        .map(|row| (row.get("table_name"), row.get("dumped_columns")))
+        .collect();
+    if tables.is_empty() {
+        return Err(format!("No tables found in schema {schema}").into());
+    }
+    let foreign_keys: Vec<ForeignKey> = sampling::foreign_keys(client, schema)?
+        .into_iter()
+        // Partitions inherit their parent's foreign keys.
+        .filter(|fk| tables.contains_key(&fk.table) && tables.contains_key(&fk.referenced_table))
+        .collect();
+    let scoped = |table: &str| tables[table].iter().any(|c| c == column_name);
+
+    let mut direct = Vec::new();
+    let mut overrides: Vec<(String, String, String)> = Vec::new();
+    let mut unreachable = Vec::new();
+    for table in tables.keys() {
+        if scoped(table) {
+            direct.push(table.as_str());
+            continue;
+        }
+        // The same columns the table would be dumped with, rather than `*`,
+        // which would include generated columns.
+        let select_list = |qualified: bool| {
+            dumped_columns[table]
+                .iter()
+                .map(|column| match qualified {
+                    true => format!("{}.{}", table.sql_identifier(), column.sql_identifier()),
+                    false => column.sql_identifier(),
+                })
+                .collect::<Vec<String>>()
+                .join(", ")
+        };
+        match scope(table, column_name, &foreign_keys, &scoped) {
+            Scope::Tenant(column) => {
+                overrides.push((
+                    table.clone(),
+                    format!("{table} is what {column_name} refers to."),
+                    format!(
+                        "select {} from {} where {} in :ids",
+                        select_list(false),
+                        table.sql_identifier(),
+                        column.sql_identifier()
+                    ),
+                ));
+            }
+            Scope::Joined(path) => {
+                let mut comment = "Through".to_owned();
+                let mut query = format!(
+                    "select {}\nfrom {}\n",
+                    select_list(true),
+                    table.sql_identifier()
+                );
+                for &index in path.iter() {
+                    let fk = &foreign_keys[index];
+                    let (from, to) = (
+                        fk.table.sql_identifier(),
+                        fk.referenced_table.sql_identifier(),
+                    );
+                    let on: Vec<String> = fk
+                        .columns
+                        .iter()
+                        .zip(&fk.referenced_columns)
+                        .map(|(column, referenced)| {
+                            write!(
+                                comment,
+                                " {}.{column} -> {}.{referenced},",
+                                fk.table, fk.referenced_table
+                            )
+                            .unwrap();
+                            format!(
+                                "{to}.{} = {from}.{}",
+                                referenced.sql_identifier(),
+                                column.sql_identifier()
+                            )
+                        })
+                        .collect();
+                    writeln!(query, "join {to} on {}", on.join(" and "))?;
+                }
+                let last = &foreign_keys[*path.last().unwrap()].referenced_table;
+                writeln!(
+                    query,
+                    "where {}.{} in :ids",
+                    last.sql_identifier(),
+                    column_name.to_owned().sql_identifier()
+                )?;
+                comment.pop();
+                comment.push('.');
+                overrides.push((table.clone(), comment, query));
+            }
+            Scope::Unreachable => unreachable.push(table.as_str()),
+        }
+    }
+
+    writeln!(
+        out,
+        "# Generated by `pg_parcel init --column {column_name}`. Review before use."
+    )?;
+    writeln!(out, "column_name = {}", toml_string(column_name))?;
+    writeln!(out, "schema_name = {}", toml_string(schema))?;
+    writeln!(
+        out,
+        "# database_url = \"postgres://localhost:5432/postgres\""
+    )?;
+    writeln!(out)?;
+    writeln!(out, "# Scoped by {column_name} directly:")?;
+    for table in direct.iter() {
+        writeln!(out, "#   {table}")?;
+    }
+    if direct.is_empty() {
+        writeln!(out, "#   (none)")?;
+    }
+    if !overrides.is_empty() {
+        writeln!(out)?;
+        writeln!(out, "[overrides]")?;
+        for (table, comment, query) in overrides.iter() {
+            writeln!(out, "# {comment}")?;
+            if query.contains('\n') {
+                writeln!(out, "{} = \"\"\"", toml_key(table))?;
+                for line in query.lines() {
+                    writeln!(out, "  {line}")?;
+                }
+                writeln!(out, "\"\"\"")?;
+            } else {
+                writeln!(out, "{} = {}", toml_key(table), toml_string(query))?;
+            }
+        }
+    }
+    if !unreachable.is_empty() {
+        writeln!(out)?;
+        writeln!(
+            out,
+            "# These tables have no {column_name} column, and no foreign keys leading to"
+        )?;
+        writeln!(
+            out,
+            "# a table with one. They are dumped in full unless you decide otherwise:"
+        )?;
+        writeln!(out, "# uncomment `skip = true`, or give them a `query`.")?;
+        for table in unreachable.iter() {
+            writeln!(out)?;
+            writeln!(out, "[tables.{}]", toml_key(table))?;
+            writeln!(out, "# skip = true")?;
+        }
+    }
+    Ok(())
+}
+
+/// How `table`, which has no `column_name` column, is tied to the tenant.
+fn scope(
+    table: &str,
+    column_name: &str,
+    foreign_keys: &[ForeignKey],
+    scoped: &impl Fn(&str) -> bool,
+) -> Scope {
+    // A table referred to by `column_name` holds the tenants themselves.
+    let tenant_columns = foreign_keys.iter().find(|fk| {
+        fk.referenced_table == table && scoped(&fk.table) && fk.columns == [column_name]
+    });
+    if let Some(fk) = tenant_columns {
+        return Scope::Tenant(fk.referenced_columns[0].clone());
+    }
+    // Breadth first, so that the shortest path is found.
+    let mut paths: BTreeMap<&str, Vec<usize>> = BTreeMap::from([(table, Vec::new())]);
+    let mut queue = VecDeque::from([table]);
+    while let Some(current) = queue.pop_front() {
+        for (index, fk) in foreign_keys.iter().enumerate() {
+            if fk.table != current || paths.contains_key(fk.referenced_table.as_str()) {
+                continue;
+            }
+            let mut path = paths[current].clone();
+            path.push(index);
+            if scoped(&fk.referenced_table) {
+                return Scope::Joined(path);
+            }
+            paths.insert(&fk.referenced_table, path);
+            queue.push_back(&fk.referenced_table);
+        }
+    }
+    Scope::Unreachable
+}
+
+fn toml_string(string: &str) -> String {
+    toml::Value::String(string.to_owned()).to_string()
+}
+
+/// A bare key if `key` can be one, otherwise a quoted key.
+fn toml_key(key: &str) -> String {
+    if !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        key.to_owned()
+    } else {
+        toml_string(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::init::*;
+
+    fn fk(table: &str, column: &str, referenced_table: &str) -> ForeignKey {
+        ForeignKey {
+            table: table.to_owned(),
+            columns: vec![column.to_owned()],
+            referenced_table: referenced_table.to_owned(),
+            referenced_columns: vec!["id".to_owned()],
+        }
+    }
+
+    #[test]
+    fn it_finds_the_shortest_path_to_a_scoped_table() {
+        let foreign_keys = vec![
+            fk("users", "customer_id", "customers"),
+            fk("comments", "file_id", "user_files"),
+            fk("comments", "author_id", "authors"),
+            fk("authors", "user_id", "users"),
+            fk("user_files", "user_id", "users"),
+        ];
+        let scoped = |table: &str| table == "users";
+        assert_eq!(
+            scope("customers", "customer_id", &foreign_keys, &scoped),
+            Scope::Tenant("id".to_owned())
+        );
+        assert_eq!(
+            scope("comments", "customer_id", &foreign_keys, &scoped),
+            Scope::Joined(vec![1, 4])
+        );
+        assert_eq!(
+            scope("countries", "customer_id", &foreign_keys, &scoped),
+            Scope::Unreachable
+        );
+    }
+}
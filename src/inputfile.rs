@@ -1,11 +1,12 @@
+use crate::suggestion::did_you_mean;
 use serde_derive::Deserialize;
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::error::Error;
 use std::fs;
 use std::path::Path;
 use std::process;
-use suggest::Suggest;
 
 #[derive(Deserialize, Debug)]
 pub struct InputFile {
@@ -13,9 +14,95 @@ pub struct InputFile {
     pub schema_name: String,
     pub database_url: Option<String>,
     pub accept_invalid_certs: Option<bool>,
+    /// Table whose primary key holds every valid ID, to check `--id` against.
+    pub root_table: Option<String>,
+    /// Query returning every valid ID, instead of `root_table`.
+    pub id_query: Option<String>,
     pub skip_tables: Option<HashSet<String>>,
+    pub include_tables: Option<HashSet<String>>,
+    /// Refuse to dump tables that aren't scoped, overridden or global.
+    pub strict: Option<bool>,
+    /// Tables to dump in full in strict mode, such as lookup tables.
+    pub global_tables: Option<Vec<String>>,
+    /// Columns other than `column_name` that refer to tenants, for
+    /// `--scan-leaks`.
+    pub tenant_columns: Option<Vec<String>>,
+    pub exclude_columns: Option<Vec<String>>,
     pub overrides: Option<HashMap<String, String>>,
+    /// Directory of `<table>.sql` files, each an override for its table.
+    pub overrides_dir: Option<String>,
+    /// SQL fragments for `{{name}}` in overrides and `where` conditions.
+    pub snippets: Option<HashMap<String, String>>,
     pub features: Option<HashSet<String>>,
+    /// Parameters for `--param`, with their defaults.
+    pub params: Option<BTreeMap<String, toml::Value>>,
+    pub incremental: Option<IncrementalConfig>,
+    pub partitioned_tables: Option<PartitionedTables>,
+    pub foreign_tables: Option<bool>,
+    pub refresh_materialized_views: Option<bool>,
+    pub view_sources: Option<HashMap<String, String>>,
+    pub limit: Option<u64>,
+    pub sample_percent: Option<f64>,
+    pub sample_seed: Option<f64>,
+    pub sample_references: Option<bool>,
+    pub tables: Option<HashMap<String, TableConfig>>,
+    pub column_filters: Option<Vec<ColumnFilter>>,
+    /// Timestamp column compared with `--since`.
+    pub since_column: Option<String>,
+}
+
+/// A condition for every table that has `column`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct ColumnFilter {
+    pub column: String,
+    #[serde(rename = "where")]
+    pub where_clause: String,
+}
+
+/// Settings for the tables matching one `[tables."<key>"]` section. Unknown
+/// keys are an error, since a misspelt setting would otherwise do nothing.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct TableConfig {
+    /// Leave the table out of the dump, or, if false, cancel a `skip` from a
+    /// less specific section.
+    pub skip: Option<bool>,
+    /// Dump the table with this query instead, as in `[overrides]`.
+    pub query: Option<String>,
+    /// Extra condition on the table's rows, on top of `column_name`.
+    #[serde(rename = "where")]
+    pub where_clause: Option<String>,
+    pub limit: Option<u64>,
+    pub sample_percent: Option<f64>,
+    /// Dump only these columns.
+    pub columns: Option<Vec<String>>,
+    /// Dump the table only if one of these features is enabled.
+    pub features: Option<Vec<String>>,
+    /// Dump the table only if none of these features are enabled.
+    pub unless_features: Option<Vec<String>>,
+    /// Order rows by this, and keep the first ones when there is a `limit`.
+    pub order_by: Option<String>,
+}
+
+/// How to dump partitioned tables.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PartitionedTables {
+    /// One `COPY` per partition, skipping partitions whose bounds rule out the
+    /// requested IDs.
+    #[default]
+    Partitions,
+    /// One `COPY` per partitioned table, through the parent.
+    Parent,
+}
+
+/// How to find changed rows for `--incremental-from`.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct IncrementalConfig {
+    /// Change column for every table that has it, such as `updated_at`.
+    pub column: Option<String>,
+    /// Change columns for specific tables, overriding `column`.
+    pub tables: Option<HashMap<String, String>>,
 }
 
 impl InputFile {
@@ -35,10 +122,10 @@ impl InputFile {
             Some(defined) => {
                 for arg in args.iter() {
                     if !defined.contains(arg) {
-                        if let Some(sugg) = defined.suggest(arg) {
-                            eprintln!("Did you mean `{}`?", sugg);
-                        }
-                        eprintln!("No feature named `{arg}` defined in input file");
+                        eprintln!(
+                            "No feature named `{arg}` defined in input file.{}",
+                            did_you_mean(defined, arg)
+                        );
                         process::exit(1);
                     }
                 }
@@ -0,0 +1,111 @@
+use crate::manifest::hex;
+use crate::sql_string::SqlString;
+use crate::{Options, Table};
+use clap::ValueEnum;
+use postgres::Client;
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::io::Write;
+
+/// How `--large-objects` restores the large objects it exports.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LargeObjects {
+    /// With the OIDs they have in the source database.
+    Keep,
+    /// With new OIDs, rewriting the columns that refer to them.
+    Remap,
+}
+
+/// Bytes of a large object read and written at a time, so that big objects
+/// don't have to fit in memory, or in a single statement.
+const CHUNK_SIZE: i32 = 1 << 20;
+
+/// Temporary table mapping source OIDs to the OIDs of the restored copies,
+/// for `--large-objects=remap`.
+pub const OID_MAP: &str = r#"pg_temp."pg_parcel.large_objects""#;
+
+/// An expression for `column` of a temporary copy of `table` with any source
+/// OID swapped for its new one.
+pub fn remapped(table: &String, column: &String) -> String {
+    let column = format!("{}.{}", table.sql_identifier(), column.sql_identifier());
+    format!("coalesce((SELECT new FROM {OID_MAP} WHERE old = {column}), {column})")
+}
+
+/// Find the large objects referred to by `oid` and `lo` columns in the rows
+/// being dumped from `tables`.
+pub fn find_large_objects(
+    client: &mut Client,
+    options: &Options,
+    tables: &[Table],
+) -> Result<BTreeSet<u32>, Box<dyn Error>> {
+    let mut oids = BTreeSet::new();
+    for table in tables.iter() {
+        for column in table.columns.iter().filter(|column| column.is_oid) {
+            let query = format!(
+                "SELECT DISTINCT pg_largeobject_metadata.oid FROM ({}) AS scoped JOIN pg_largeobject_metadata ON pg_largeobject_metadata.oid = scoped.{}::oid",
+                table.copy_out_query(options),
+                column.name.sql_identifier()
+            );
+            for row in client.query(&query, &[])? {
+                oids.insert(row.get(0));
+            }
+        }
+    }
+    Ok(oids)
+}
+
+/// Write statements recreating each of the large objects in `oids` from its
+/// contents in the source database, one chunk at a time.
+///
+/// With `replace`, large objects that already exist in the target under the
+/// same OID are removed first, as `--truncate` does for tables.
+pub fn write_large_objects(
+    out: &mut impl Write,
+    client: &mut Client,
+    oids: &BTreeSet<u32>,
+    mode: LargeObjects,
+    replace: bool,
+) -> Result<(), Box<dyn Error>> {
+    if mode == LargeObjects::Remap {
+        writeln!(
+            out,
+            "CREATE TEMPORARY TABLE {OID_MAP} (old oid PRIMARY KEY, new oid NOT NULL);"
+        )?;
+    }
+    for oid in oids.iter() {
+        let target = match mode {
+            LargeObjects::Keep => {
+                if replace {
+                    writeln!(
+                        out,
+                        "SELECT lo_unlink(oid) FROM pg_largeobject_metadata WHERE oid = {oid};"
+                    )?;
+                }
+                writeln!(out, "SELECT lo_create({oid});")?;
+                oid.to_string()
+            }
+            LargeObjects::Remap => {
+                writeln!(out, "INSERT INTO {OID_MAP} VALUES ({oid}, lo_create(0));")?;
+                format!("(SELECT new FROM {OID_MAP} WHERE old = {oid})")
+            }
+        };
+        let mut offset: i64 = 0;
+        loop {
+            let data: Vec<u8> = client
+                .query_one("SELECT lo_get($1, $2, $3)", &[oid, &offset, &CHUNK_SIZE])?
+                .get(0);
+            if !data.is_empty() {
+                writeln!(
+                    out,
+                    "SELECT lo_put({target}, {offset}, '\\x{}'::bytea);",
+                    hex(&data)
+                )?;
+            }
+            if data.len() < CHUNK_SIZE as usize {
+                break;
+            }
+            offset += data.len() as i64;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,196 @@
+use crate::parcel::split_row;
+use crate::{Options, Table};
+use postgres::Client;
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::io::{self, Write};
+
+/// Settings for scanning dumped rows for other tenants' data.
+#[derive(Debug, Clone)]
+pub struct LeakScan {
+    /// `column_name`, and the columns in `tenant_columns`.
+    pub columns: Vec<String>,
+    /// Fail the run if anything is found.
+    pub fail: bool,
+}
+
+/// Passes a `COPY` text stream through to `inner`, counting values in the
+/// tenant columns that aren't one of the requested IDs. `NULL`s are allowed,
+/// since rows where `column_name` is `NULL` are in scope.
+pub struct LeakScanner<W: Write> {
+    inner: W,
+    /// Positions of the tenant columns among the block's columns, with the
+    /// IDs as each column holds them.
+    checks: Vec<(usize, Vec<String>)>,
+    /// The row read so far.
+    line: Vec<u8>,
+    pub leaks: u64,
+    /// A few of the values found, for the report.
+    pub examples: BTreeSet<String>,
+}
+
+impl<W: Write> LeakScanner<W> {
+    /// Scan the columns of `ids`, from `tenant_ids`, among `columns`.
+    pub fn new(inner: W, columns: &[String], mut ids: HashMap<String, Vec<String>>) -> Self {
+        let checks = columns
+            .iter()
+            .enumerate()
+            .filter_map(|(position, column)| Some((position, ids.remove(column)?)))
+            .collect();
+        LeakScanner {
+            inner,
+            checks,
+            line: Vec::new(),
+            leaks: 0,
+            examples: BTreeSet::new(),
+        }
+    }
+
+    fn check_line(&mut self) {
+        let fields = split_row(&String::from_utf8_lossy(&self.line));
+        for (position, ids) in self.checks.iter() {
+            let Some(Some(value)) = fields.get(*position) else {
+                continue;
+            };
+            if !ids.contains(value) {
+                self.leaks += 1;
+                if self.examples.len() < 3 {
+                    self.examples.insert(value.clone());
+                }
+            }
+        }
+    }
+}
+
+impl<W: Write> Write for LeakScanner<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        if self.checks.is_empty() {
+            return Ok(written);
+        }
+        for &byte in buf[..written].iter() {
+            if byte == b'\n' {
+                self.check_line();
+                self.line.clear();
+            } else {
+                self.line.push(byte);
+            }
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The requested IDs as each of `table`'s tenant columns holds them, by
+/// column. IDs are cast to the column's type, as they are in the dump's scope
+/// condition, so that `007` matches `7` in an integer column and upper case
+/// UUIDs match their lower case form. IDs that don't cast are compared as
+/// given.
+pub fn tenant_ids(
+    client: &mut Client,
+    options: &Options,
+    scan: &LeakScan,
+    table: &Table,
+) -> Result<HashMap<String, Vec<String>>, Box<dyn Error>> {
+    let columns = client.query(
+        "select attname::text, format_type(atttypid, atttypmod) from pg_attribute where attrelid = to_regclass($1) and attname = any($2) and not attisdropped",
+        &[&table.sql_identifier(), &scan.columns],
+    )?;
+    let mut ids = HashMap::new();
+    for column in columns.into_iter() {
+        let column_type: String = column.get(1);
+        // A failed cast would abort the snapshot's transaction, so try it in
+        // a savepoint.
+        client.batch_execute("SAVEPOINT pg_parcel_tenant_ids")?;
+        let cast = client.query_one(
+            &format!(
+                "SELECT array(SELECT id::{column_type}::text FROM unnest($1::text[]) AS ids (id))"
+            ),
+            &[&options.column_values],
+        );
+        let column_ids = match cast {
+            Ok(row) => {
+                client.batch_execute("RELEASE SAVEPOINT pg_parcel_tenant_ids")?;
+                row.get(0)
+            }
+            Err(_) => {
+                client.batch_execute("ROLLBACK TO SAVEPOINT pg_parcel_tenant_ids")?;
+                options.column_values.clone()
+            }
+        };
+        ids.insert(column.get(0), column_ids);
+    }
+    Ok(ids)
+}
+
+/// Scan the rows each of `tables` will be dumped with, without writing them,
+/// so that `--fail-on-leak` can fail before any data is output.
+pub fn check_tables(
+    client: &mut Client,
+    options: &Options,
+    scan: &LeakScan,
+    tables: &[Table],
+) -> Result<(), Box<dyn Error>> {
+    let mut leaks = 0;
+    for table in tables.iter() {
+        let ids = tenant_ids(client, options, scan, table)?;
+        let mut reader = client.copy_out(&format!(
+            "COPY ({}) TO stdout;",
+            table.copy_out_query(options)
+        ))?;
+        let mut scanner = LeakScanner::new(io::sink(), &table.column_names(), ids);
+        io::copy(&mut reader, &mut scanner)?;
+        if scanner.leaks > 0 {
+            let examples: Vec<&String> = scanner.examples.iter().collect();
+            eprintln!(
+                "Leak scan: {} has {} values from other tenants, such as {examples:?}",
+                table.name, scanner.leaks
+            );
+            leaks += scanner.leaks;
+        }
+    }
+    if leaks > 0 {
+        return Err(format!(
+            "Leak scan found {leaks} values from other tenants; nothing was written"
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::leaks::*;
+
+    #[test]
+    fn it_counts_other_tenants_values() {
+        let columns = ["id", "customer_id", "owner_id", "body"].map(String::from);
+        let ids = HashMap::from([
+            (
+                "customer_id".to_owned(),
+                vec!["acme".to_owned(), "a\tb".to_owned()],
+            ),
+            ("owner_id".to_owned(), vec!["acme".to_owned()]),
+        ]);
+        let mut out = Vec::new();
+        let mut scanner = LeakScanner::new(&mut out, &columns, ids);
+        scanner
+            .write_all(b"1\tacme\t\\N\tx\n2\ta\\tb\tglo")
+            .unwrap();
+        scanner
+            .write_all(b"bex\tacme\n3\tinitech\tacme\t\\N\n4\t\\x\t\\N\t\\N\n")
+            .unwrap();
+        assert_eq!(scanner.leaks, 3);
+        assert_eq!(
+            scanner.examples.iter().collect::<Vec<_>>(),
+            ["globex", "initech", "x"]
+        );
+        assert_eq!(
+            out,
+            b"1\tacme\t\\N\tx\n2\ta\\tb\tglobex\tacme\n3\tinitech\tacme\t\\N\n4\t\\x\t\\N\t\\N\n"
+        );
+    }
+}
@@ -1,13 +1,51 @@
+mod columns;
+mod diff;
+mod estimate;
+mod id_check;
+mod incremental;
+mod init;
 mod inputfile;
+mod large_objects;
+mod leaks;
+mod manifest;
+mod matviews;
+mod overrides_dir;
+mod params;
+mod parcel;
+mod sampling;
+mod schema;
+mod selected;
+mod selection;
+mod sequences;
+mod snippets;
 mod sql_string;
+mod suggestion;
+mod table_config;
+mod verify;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use columns::ColumnExclusions;
+use diff::DiffFormat;
+use estimate::RowCount;
+use id_check::IdCheck;
+use incremental::Incremental;
 use indicatif::{ProgressBar, ProgressStyle};
-use inputfile::InputFile;
+use inputfile::{ColumnFilter, InputFile, PartitionedTables};
 use itertools::intersperse;
+use large_objects::LargeObjects;
 use lazy_static::lazy_static;
-use postgres::Client;
-use regex::{Regex, RegexSet};
+use leaks::{LeakScan, LeakScanner};
+use manifest::{CopyWriter, Manifest, TableManifest};
+use overrides_dir::OverrideFile;
+use params::Params;
+use parcel::Parcel;
+use postgres::{Client, Row};
+use regex::Regex;
+use sampling::{Sample, Sampling};
+use schema::Section;
+use selection::{Rule, TableSelection, Verdict};
+use sequences::SequenceReset;
+use snippets::Snippets;
 use sql_string::SqlString;
 use std::collections::{HashMap, HashSet};
 use std::error::Error;
@@ -15,11 +53,16 @@ use std::io::Write;
 use std::path::Path;
 use std::sync::Arc;
 use std::time::Duration;
+use table_config::{TableConfigs, TableSettings};
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
+#[clap(subcommand_negates_reqs = true)]
 /// Command line arguments
 struct Args {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
     /// Configuration file
     #[clap(short, long, display_order = 1)]
     #[clap(default_value_t = String::from("./pg_parcel.toml"))]
@@ -33,9 +76,29 @@ struct Args {
     ids: Vec<String>,
 
     /// Override database URL in parcel config.
-    #[clap(long, display_order = 3)]
+    #[clap(long, global = true, display_order = 3)]
     database_url: Option<String>,
 
+    /// Dump only tables matching this pattern, ignoring `include_tables` and
+    /// `skip_tables` in pg_parcel.toml.
+    ///
+    /// PATTERN is a regular expression that must match the whole table name.
+    /// Can be given more than once.
+    #[clap(short = 't', long = "table", value_name = "PATTERN", display_order = 3)]
+    tables: Vec<String>,
+
+    /// Don't dump tables matching this pattern, even if --table matches them.
+    ///
+    /// PATTERN is a regular expression that must match the whole table name.
+    /// Can be given more than once.
+    #[clap(
+        short = 'T',
+        long = "exclude-table",
+        value_name = "PATTERN",
+        display_order = 3
+    )]
+    exclude_tables: Vec<String>,
+
     /// Insert a `TRUNCATE` command before any `COPY` commands.
     ///
     /// This will truncate every table found in the schema *except* those that
@@ -47,6 +110,26 @@ struct Args {
     #[clap(long, display_order = 4)]
     truncate: bool,
 
+    /// Refuse to dump any table that isn't scoped by `column_name`, dumped with
+    /// an override, or listed in `global_tables`, as `strict = true` in
+    /// pg_parcel.toml does.
+    ///
+    /// Without this, such tables are dumped in full, which for a table added
+    /// since the config was written may mean every tenant's rows.
+    #[clap(long, display_order = 4)]
+    strict: bool,
+
+    /// Check every dumped value of `column_name`, and of the columns in
+    /// `tenant_columns` in pg_parcel.toml, against the requested IDs, and
+    /// report values from other tenants per table.
+    #[clap(long, display_order = 4)]
+    scan_leaks: bool,
+
+    /// Like --scan-leaks, but fail if anything is found. The rows are scanned
+    /// before anything is written, so nothing is output on failure.
+    #[clap(long, display_order = 4)]
+    fail_on_leak: bool,
+
     /// Prints a report estimating row count and size of the data to be dumped
     /// for each table, and in total. Does not dump table data.
     ///
@@ -56,6 +139,24 @@ struct Args {
     #[clap(long, display_order = 10)]
     estimate_only: bool,
 
+    /// With `--estimate-only`, read row counts from the query planner via
+    /// `EXPLAIN` instead of running `SELECT COUNT(*)` for every table.
+    ///
+    /// This is much faster on large tenants, but the figures are only as good
+    /// as the planner's statistics, so they are reported as approximate.
+    #[clap(long, requires = "estimate_only", display_order = 11)]
+    fast_estimate: bool,
+
+    /// With `--fast-estimate`, spend up to this many seconds counting rows
+    /// exactly, starting with the tables the planner expects to be smallest.
+    #[clap(
+        long,
+        value_name = "SECONDS",
+        requires = "fast_estimate",
+        display_order = 12
+    )]
+    exact_count_budget: Option<f64>,
+
     /// Populate session variable `pg_parcel.features` with these strings. If
     /// set, it takes precedence over the default_features in pg_parcel.toml
     #[clap(long, value_delimiter = ',', display_order = 5)]
@@ -65,25 +166,202 @@ struct Args {
     /// and pg_parcel.toml.
     #[clap(long = "no-feature", value_delimiter = ',', display_order = 6)]
     skipped_features: Option<Vec<String>>,
+
+    /// Set a parameter declared in the `[params]` section of pg_parcel.toml.
+    ///
+    /// The value is parsed as the same type as the parameter's default, set as
+    /// session variable `pg_parcel.param.NAME`, and replaces `:NAME` in
+    /// overrides and `where` conditions. Can be given more than once.
+    #[clap(long = "param", value_name = "NAME=VALUE", display_order = 6)]
+    params: Vec<String>,
+
+    /// Also write the parcel's manifest to this file, as TOML.
+    ///
+    /// The same information is always embedded in the dump as SQL comments.
+    #[clap(long, value_name = "FILE", display_order = 7)]
+    manifest: Option<String>,
+
+    /// Make a delta parcel containing only what changed since a previous one.
+    ///
+    /// FILE is the previous parcel, or its `--manifest`. Rows are selected by
+    /// the change columns in the `[incremental]` section of pg_parcel.toml,
+    /// and restored by upserting on the primary key. Rows that are no longer
+    /// in scope are deleted. Tables without a change column are sent in full;
+    /// tables without a primary key are deleted and reloaded.
+    #[clap(
+        long,
+        value_name = "FILE",
+        conflicts_with = "truncate",
+        display_order = 8
+    )]
+    incremental_from: Option<String>,
+
+    /// Include DDL for the dumped tables, so that the parcel can be restored
+    /// into an empty database.
+    ///
+    /// Tables, sequences and defaults are created before the data; indexes,
+    /// constraints and triggers after it. The DDL comes from `pg_dump
+    /// --schema-only`, which must be on the `PATH`, and is read from the same
+    /// snapshot as the data. Types, functions and extensions the tables
+    /// depend on are not included.
+    #[clap(long, conflicts_with = "incremental_from", display_order = 9)]
+    with_schema: bool,
+
+    /// Dump only rows whose `since_column`, set in pg_parcel.toml, is at or
+    /// after this time. Tables without that column are dumped in full.
+    #[clap(long, value_name = "TIMESTAMP", display_order = 9)]
+    since: Option<String>,
+
+    /// Move sequences forward after loading data, so that new rows don't
+    /// collide with restored ones.
+    ///
+    /// Covers sequences owned by dumped columns, identity columns, and
+    /// sequences used in dumped columns' defaults. With `max`, each sequence
+    /// is set to the largest value loaded into any of its columns; with
+    /// `source`, to its current value in the source database.
+    #[clap(
+        long,
+        value_enum,
+        value_name = "FROM",
+        num_args = 0..=1,
+        default_missing_value = "max",
+        display_order = 9
+    )]
+    reset_sequences: Option<SequenceReset>,
+
+    /// Export the large objects referred to by `oid` and `lo` columns in the
+    /// dumped rows.
+    ///
+    /// They are restored with `lo_create` and `lo_put`, 1 MiB at a time. With
+    /// `keep`, they get the same OIDs as in the source database, replacing any
+    /// existing ones when used with --truncate or --incremental-from. With
+    /// `remap`, they get new OIDs, and the referring columns are rewritten to
+    /// match as rows are loaded.
+    #[clap(
+        long,
+        value_enum,
+        value_name = "OIDS",
+        num_args = 0..=1,
+        default_missing_value = "keep",
+        display_order = 9
+    )]
+    large_objects: Option<LargeObjects>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// List the tables in the schema, whether each would be dumped, and the
+    /// rule that decided it.
+    ListTables,
+    /// Print a starter pg_parcel.toml for the database, worked out from its
+    /// tables and foreign keys.
+    ///
+    /// Tables with COLUMN are scoped by it. Tables it refers to, and tables
+    /// that reach a table with it through foreign keys, get overrides. Tables
+    /// that do neither are listed as needing a decision. Connects to
+    /// --database-url.
+    Init {
+        /// Column identifying the tenant, such as `customer_id`.
+        #[clap(long)]
+        column: String,
+
+        /// Schema to look at.
+        #[clap(long, default_value = "public")]
+        schema: String,
+
+        /// Accept invalid TLS certificates from the database.
+        #[clap(long)]
+        accept_invalid_certs: bool,
+    },
+    /// Check that a parcel file matches its manifest.
+    ///
+    /// Every `COPY` block is re-hashed and compared with the checksum recorded
+    /// when the parcel was made. Missing, truncated, altered and unexpected
+    /// tables are reported, and the exit status is non-zero if there are any.
+    Verify {
+        /// Parcel file to check.
+        file: String,
+
+        /// Read the manifest from this file instead of from the parcel.
+        #[clap(long, value_name = "FILE")]
+        manifest: Option<String>,
+    },
+
+    /// Compare two parcels, or a parcel with the live database.
+    ///
+    /// Rows are matched by primary key, as recorded in the manifest, and
+    /// reported per table as inserted, deleted or changed. Tables without a
+    /// primary key are matched on all of their columns.
+    Diff {
+        /// Parcel to compare from.
+        old: String,
+
+        /// Parcel to compare to. If omitted, compare with the database in the
+        /// configuration file by re-running the queries recorded in OLD's
+        /// manifest, with the same IDs and features.
+        new: Option<String>,
+
+        /// Output format.
+        #[clap(long, value_enum, default_value_t = DiffFormat::Text)]
+        format: DiffFormat,
+    },
+}
+
+/// Replace `:ids` in a query from the config file with the requested
+/// `column_values`.
+fn substitute_placeholders(query: &str, options: &Options) -> String {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r":ids\b").unwrap();
+    }
+    let query = selected::substitute(query);
+    let query = RE.replace_all(&query, format!("({})", options.sql_column_values()));
+    options.params.substitute(&query).to_string()
 }
 
 /// Options here is a combination of command line arguments and contents of the slicefile.
+#[derive(Default)]
 struct Options {
     column_name: String,
     column_values: Vec<String>,
     schema: String,
     database_url: String,
     accept_invalid_certs: bool,
-    skip_tables: RegexSet,
-    overrides: HashMap<String, String>,
+    /// How to check that the requested IDs exist before dumping.
+    id_check: Option<IdCheck>,
+    selection: TableSelection,
+    exclude_columns: ColumnExclusions,
+    strict: bool,
+    /// Tables that may be dumped in full in strict mode.
+    global_tables: Vec<Regex>,
+    leak_scan: Option<LeakScan>,
+    /// Per-table settings, from `[tables]` and the keys that are shorthand
+    /// for it.
+    tables: TableConfigs,
+    /// Overrides read from `overrides_dir`, also in `tables`.
+    override_files: Vec<OverrideFile>,
+    partitioned_tables: PartitionedTables,
+    foreign_tables: bool,
+    refresh_materialized_views: bool,
+    /// Views to dump tables from, by table name.
+    view_sources: HashMap<String, String>,
+    /// Conditions for every table with a given column, including `--since`.
+    column_filters: Vec<ColumnFilter>,
+    sampling: Sampling,
     estimate_only: bool,
+    fast_estimate: bool,
+    exact_count_budget: Option<Duration>,
     truncate: bool,
     features: HashSet<String>,
+    params: Params,
+    manifest: Option<String>,
+    incremental: Option<Incremental>,
+    with_schema: bool,
+    reset_sequences: Option<SequenceReset>,
+    large_objects: Option<LargeObjects>,
 }
 
 impl Options {
-    pub fn load() -> Result<Options, Box<dyn Error>> {
-        let args = Args::parse();
+    pub fn load(args: Args) -> Result<Options, Box<dyn Error>> {
         let file = InputFile::load(Path::new(&args.file))?;
 
         // Features requested at the command-line take precedence, then the
@@ -104,6 +382,84 @@ impl Options {
             }
         }
 
+        let incremental = match &args.incremental_from {
+            Some(path) => {
+                let (incremental, previous) = Incremental::load(
+                    Path::new(path),
+                    file.incremental.clone().unwrap_or_default(),
+                )?;
+                let mut previous_ids = previous.ids.clone();
+                let mut ids = args.ids.clone();
+                previous_ids.sort();
+                ids.sort();
+                if previous.schema != file.schema_name
+                    || previous.column_name != file.column_name
+                    || previous_ids != ids
+                {
+                    return Err(format!(
+                        "{path} was made for {}.{} in {:?}, not {}.{} in {:?}",
+                        previous.schema,
+                        previous.column_name,
+                        previous.ids,
+                        file.schema_name,
+                        file.column_name,
+                        args.ids
+                    )
+                    .into());
+                }
+                // Features decide which tables are dumped, and overrides can
+                // read them, so rows could be deleted for being out of scope.
+                let mut previous_features = previous.features.clone();
+                let mut current_features: Vec<String> = features.iter().cloned().collect();
+                previous_features.sort();
+                current_features.sort();
+                if previous_features != current_features {
+                    return Err(format!(
+                        "{path} was made with features {previous_features:?}, not {current_features:?}"
+                    )
+                    .into());
+                }
+                Some(incremental)
+            }
+            None => None,
+        };
+
+        let mut params = Params::new(file.params.clone().unwrap_or_default())?;
+        params.set(&args.params)?;
+
+        let sampling = Sampling::load(&file)?;
+        let snippets = Snippets::new(file.snippets.clone().unwrap_or_default());
+        let override_files = match &file.overrides_dir {
+            // Relative to the config file.
+            Some(dir) => overrides_dir::load(
+                &Path::new(&args.file)
+                    .parent()
+                    .unwrap_or(Path::new("."))
+                    .join(dir),
+            )?,
+            None => Vec::new(),
+        };
+        let tables = TableConfigs::load(&file, &override_files, &snippets)?;
+
+        let mut column_filters = file.column_filters.clone().unwrap_or_default();
+        for filter in column_filters.iter_mut() {
+            filter.where_clause = snippets.expand(&filter.where_clause)?;
+        }
+        if let Some(since) = &args.since {
+            let column = file
+                .since_column
+                .clone()
+                .ok_or("To use --since, set since_column in pg_parcel.toml first")?;
+            column_filters.push(since_filter(column, since));
+        }
+
+        let leak_scan = (args.scan_leaks || args.fail_on_leak).then(|| LeakScan {
+            columns: std::iter::once(file.column_name.clone())
+                .chain(file.tenant_columns.clone().unwrap_or_default())
+                .collect(),
+            fail: args.fail_on_leak,
+        });
+
         let options = Options {
             column_name: file.column_name,
             column_values: args.ids,
@@ -113,20 +469,75 @@ impl Options {
                 .unwrap_or_else(|| "postgres://localhost:5432/postgres".to_string()),
             schema: file.schema_name,
             accept_invalid_certs: file.accept_invalid_certs.unwrap_or(false),
-            skip_tables: match file.skip_tables {
-                Some(patterns) => RegexSet::new(patterns)?,
-                None => RegexSet::empty(),
-            },
-            overrides: file.overrides.unwrap_or_default(),
+            id_check: IdCheck::new(file.root_table, file.id_query)?,
+            tables,
+            override_files,
+            selection: TableSelection::new(file.include_tables, args.tables, args.exclude_tables)?,
+            exclude_columns: ColumnExclusions::new(file.exclude_columns.unwrap_or_default())?,
+            strict: args.strict || file.strict.unwrap_or(false),
+            leak_scan,
+            global_tables: file
+                .global_tables
+                .iter()
+                .flatten()
+                .map(|pattern| table_config::glob(pattern))
+                .collect::<Result<_, _>>()?,
+            partitioned_tables: file.partitioned_tables.unwrap_or_default(),
+            foreign_tables: file.foreign_tables.unwrap_or(false),
+            refresh_materialized_views: file.refresh_materialized_views.unwrap_or(false),
+            view_sources: file.view_sources.unwrap_or_default(),
+            column_filters,
+            sampling,
             estimate_only: args.estimate_only,
+            fast_estimate: args.fast_estimate,
+            exact_count_budget: args
+                .exact_count_budget
+                .map(Duration::try_from_secs_f64)
+                .transpose()?,
             truncate: args.truncate,
             features,
+            params,
+            manifest: args.manifest,
+            incremental,
+            with_schema: args.with_schema,
+            reset_sequences: args.reset_sequences,
+            large_objects: args.large_objects,
         };
         Ok(options)
     }
+
+    /// Decide whether to dump the table called `name`, a partition of
+    /// `ancestors` if it has any.
+    fn verdict(&self, name: &str, ancestors: &[String]) -> Verdict {
+        let settings = self.tables.settings(name, ancestors);
+        self.selection
+            .verdict(name, ancestors, &settings, &self.features)
+    }
+
+    /// Whether `global_tables` lists the table called `name`, or any of its
+    /// `ancestors`.
+    fn is_global(&self, name: &str, ancestors: &[String]) -> bool {
+        std::iter::once(name)
+            .chain(ancestors.iter().map(String::as_str))
+            .any(|name| {
+                self.global_tables
+                    .iter()
+                    .any(|global| global.is_match(name))
+            })
+    }
+
+    /// `column_values` as a comma-separated list of SQL literals.
+    fn sql_column_values(&self) -> String {
+        let column_values = self.column_values.iter().map(|s| s.sql_value());
+        intersperse(column_values, ",".to_string()).collect::<String>()
+    }
 }
 
 fn pg_client(options: &Options) -> Result<Client, Box<dyn Error>> {
+    connect(&options.database_url, options.accept_invalid_certs)
+}
+
+fn connect(database_url: &str, accept_invalid_certs: bool) -> Result<Client, Box<dyn Error>> {
     mod danger {
         pub struct NoCertificateVerification {}
 
@@ -149,23 +560,66 @@ fn pg_client(options: &Options) -> Result<Client, Box<dyn Error>> {
         .with_safe_defaults()
         .with_root_certificates(rustls::RootCertStore::empty())
         .with_no_client_auth();
-    if options.accept_invalid_certs {
+    if accept_invalid_certs {
         config
             .dangerous()
             .set_certificate_verifier(Arc::new(danger::NoCertificateVerification {}));
     }
     let tls = tokio_postgres_rustls::MakeRustlsConnect::new(config);
-    Ok(Client::connect(&options.database_url, tls)?)
+    Ok(Client::connect(database_url, tls)?)
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let options = Options::load()?;
+/// When the dump's snapshot was taken, and how a later delta parcel can find
+/// what changed since.
+struct Snapshot {
+    /// Start of the dump transaction, in UTC.
+    time: String,
+    /// See `Manifest::watermark`.
+    watermark: String,
+    /// `pg_current_snapshot()`, on PostgreSQL 13 and later.
+    pg_snapshot: Option<String>,
+}
 
-    let mut client = pg_client(&options)?;
+/// Format of the UTC times in the manifest, which parse as `timestamptz`
+/// whatever the session's `TimeZone` and `DateStyle`.
+const UTC_FORMAT: &str = r#"'YYYY-MM-DD"T"HH24:MI:SS.US"Z"'"#;
 
+/// Open the read-only snapshot everything is dumped from, and populate the
+/// `pg_parcel.*` session variables.
+fn begin_session(client: &mut Client, options: &Options) -> Result<Snapshot, Box<dyn Error>> {
     // Restrict `search_path` to just the one schema.
     client.execute(&format!("SET SCHEMA {}", options.schema.sql_value()), &[])?;
+    // Transactions still open when the snapshot is taken may commit rows
+    // stamped with an earlier time, which the snapshot can't see. Any such
+    // transaction is either open now, or starts after now.
+    let watermark: String = client
+        .query_one(
+            &format!(
+                "SELECT to_char(least(clock_timestamp(), min(xact_start)) AT TIME ZONE 'UTC', {UTC_FORMAT})
+                 FROM pg_stat_activity WHERE pid <> pg_backend_pid()"
+            ),
+            &[],
+        )?
+        .get(0);
     client.execute("BEGIN ISOLATION LEVEL REPEATABLE READ READ ONLY;", &[])?;
+    let time: String = client
+        .query_one(
+            &format!("SELECT to_char(now() AT TIME ZONE 'UTC', {UTC_FORMAT})"),
+            &[],
+        )?
+        .get(0);
+    let version: i32 = client
+        .query_one("SELECT current_setting('server_version_num')::int", &[])?
+        .get(0);
+    let pg_snapshot: Option<String> = if version >= 130000 {
+        Some(
+            client
+                .query_one("SELECT pg_current_snapshot()::text", &[])?
+                .get(0),
+        )
+    } else {
+        None
+    };
 
     // Populate features settings
     client.execute(
@@ -183,6 +637,15 @@ fn main() -> Result<(), Box<dyn Error>> {
     for feature in options.features.iter() {
         client.execute(&format!("SET pg_parcel.feature.{feature} = true"), &[])?;
     }
+    for (name, value) in options.params.texts() {
+        client.execute(
+            &format!(
+                "SET pg_parcel.param.{name} = {}",
+                value.replace('\'', "''").sql_value()
+            ),
+            &[],
+        )?;
+    }
 
     client.execute(
         &format!(
@@ -192,8 +655,86 @@ fn main() -> Result<(), Box<dyn Error>> {
         &[],
     )?;
 
+    Ok(Snapshot {
+        time,
+        watermark,
+        pg_snapshot,
+    })
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = Args::parse();
+    match args.command.take() {
+        Some(Command::ListTables) => {
+            let options = Options::load(args)?;
+            let mut client = pg_client(&options)?;
+            return selection::list_tables(&mut client, &options);
+        }
+        Some(Command::Init {
+            column,
+            schema,
+            accept_invalid_certs,
+        }) => {
+            let database_url = args
+                .database_url
+                .unwrap_or_else(|| "postgres://localhost:5432/postgres".to_string());
+            let mut client = connect(&database_url, accept_invalid_certs)?;
+            return init::write_config(&mut client, &mut std::io::stdout(), &schema, &column);
+        }
+        Some(Command::Verify { file, manifest }) => {
+            return verify::verify(Path::new(&file), manifest.as_deref().map(Path::new));
+        }
+        Some(Command::Diff { old, new, format }) => {
+            let old = Parcel::load(Path::new(&old), true)?;
+            let new = match new {
+                Some(new) => Parcel::load(Path::new(&new), true)?,
+                None => {
+                    let manifest = old
+                        .manifest
+                        .clone()
+                        .ok_or("No manifest found in parcel; cannot compare with the database")?;
+                    let mut options = Options::load(args)?;
+                    options.schema = manifest.schema.clone();
+                    options.column_values = manifest.ids.clone();
+                    options.features = manifest.features.iter().cloned().collect();
+                    options.params.set(
+                        &manifest
+                            .params
+                            .iter()
+                            .map(|(name, value)| format!("{name}={value}"))
+                            .collect::<Vec<String>>(),
+                    )?;
+                    let tables = get_tables(&options)?;
+                    let mut client = pg_client(&options)?;
+                    selected::create_temp_tables(&mut client, &options, &tables)?;
+                    begin_session(&mut client, &options)?;
+                    selected::fill_temp_tables(&mut client, &options, &tables)?;
+                    let live = diff::read_live(&mut client, &manifest, &old)?;
+                    client.query("ROLLBACK", &[])?;
+                    live
+                }
+            };
+            return diff::report(&diff::diff(&old, &new), format);
+        }
+        None => {}
+    }
+    let options = Options::load(args)?;
+
     let tables = get_tables(&options)?;
 
+    let mut client = pg_client(&options)?;
+    selected::create_temp_tables(&mut client, &options, &tables)?;
+    let snapshot = begin_session(&mut client, &options)?;
+    if let Some(check) = &options.id_check {
+        id_check::check_ids(&mut client, &options, check, &tables)?;
+    }
+    selected::fill_temp_tables(&mut client, &options, &tables)?;
+    if !options.estimate_only {
+        if let Some(scan) = options.leak_scan.as_ref().filter(|scan| scan.fail) {
+            leaks::check_tables(&mut client, &options, scan, &tables)?;
+        }
+    }
+
     let pb = ProgressBar::new(tables.len() as u64);
     let pb_template = format!(
         "{{msg:>{width}.bold}} {{spinner:.blue/white}} {{wide_bar:.blue/white}} eta {{eta}}",
@@ -213,23 +754,35 @@ fn main() -> Result<(), Box<dyn Error>> {
     if options.estimate_only {
         let mut total_size: u64 = 0; // Estimate in kibibytes.
 
+        let fast_row_counts = if options.fast_estimate {
+            Some(estimate::fast_row_counts(
+                &mut client,
+                &tables,
+                &options,
+                &pb,
+            )?)
+        } else {
+            None
+        };
+
         pb.println("        Rows / Total |         |  Size estimate | Table name");
-        for table in tables.iter() {
-            let count_statement = format!(
-                // The `postgres` crate does not define `FromSql for u64` (or
-                // usize, or u128), so it would appear that the only safe way to
-                // query a PostgreSQL `int8` is as text.
-                "SELECT COUNT(*)::text FROM ({}) AS query",
-                table.copy_out_query(&options)
-            );
+        let mut approximate = false;
+        for (index, table) in tables.iter().enumerate() {
             pb.set_message(table.name.to_owned());
-            let row_count_s: String = client.query_one(&count_statement, &[])?.get(0);
-            let row_count: u64 = row_count_s.parse()?;
-            let row_selectivity = (100f64 * row_count as f64 / table.rows as f64)
+            let row_count = match &fast_row_counts {
+                Some(row_counts) => row_counts[index],
+                None => RowCount::Exact(estimate::exact_row_count(
+                    &mut client,
+                    &table.copy_out_query(&options),
+                )?),
+            };
+            approximate |= row_count.is_approximate();
+            let row_selectivity = (100f64 * row_count.value() as f64 / table.rows as f64)
                 .max(0.0) // Deal with NAN.
                 .clamp(0.0, 100.0);
             let size_estimate = if table.rows > 0 {
-                let size = (row_count as f64 * table.size as f64) / (table.rows as f64 * 1024f64);
+                let size =
+                    (row_count.value() as f64 * table.size as f64) / (table.rows as f64 * 1024f64);
                 size.max(0.0) as u64 // Deal with NAN.
             } else {
                 0u64
@@ -242,9 +795,58 @@ fn main() -> Result<(), Box<dyn Error>> {
             pb.inc(1);
             total_size = total_size.saturating_add(size_estimate);
         }
-        pb.finish_with_message(format!("Total size estimated at: {total_size} kiB"));
+        if approximate {
+            pb.println("Row counts prefixed with ~ are the query planner's estimates.");
+            pb.finish_with_message(format!("Total size estimated at: ~{total_size} kiB"));
+        } else {
+            pb.finish_with_message(format!("Total size estimated at: {total_size} kiB"));
+        }
     } else {
-        let mut sizes: Vec<(String, u64)> = Vec::with_capacity(tables.len());
+        let server_version: String = client.query_one("SHOW server_version", &[])?.get(0);
+        let mut features: Vec<String> = options.features.iter().cloned().collect();
+        features.sort();
+        let mut manifest = Manifest {
+            pg_parcel_version: env!("CARGO_PKG_VERSION").to_owned(),
+            server_version,
+            schema: options.schema.clone(),
+            column_name: options.column_name.clone(),
+            ids: options.column_values.clone(),
+            features,
+            params: options.params.texts(),
+            snapshot: snapshot.time,
+            watermark: Some(snapshot.watermark),
+            pg_snapshot: snapshot.pg_snapshot,
+            since: options
+                .incremental
+                .as_ref()
+                .map(|incremental| incremental.since.clone()),
+            tables: Vec::with_capacity(tables.len()),
+        };
+        manifest.write_header(&mut std::io::stdout())?;
+
+        let materialized_views = if options.refresh_materialized_views {
+            matviews::find_materialized_views(&mut client, &options)?
+        } else {
+            Vec::new()
+        };
+
+        // Let `pg_dump` see exactly what we see.
+        let exported_snapshot: Option<String> = if options.with_schema {
+            Some(client.query_one("SELECT pg_export_snapshot()", &[])?.get(0))
+        } else {
+            None
+        };
+        if let Some(exported_snapshot) = &exported_snapshot {
+            schema::write_schema(
+                &mut std::io::stdout(),
+                &options,
+                &tables,
+                &materialized_views,
+                exported_snapshot,
+                Section::PreData,
+            )?;
+        }
+
         // Disable triggers and FK constraint checks.
         writeln!(std::io::stdout(), "SET session_replication_role = replica;")?;
 
@@ -267,25 +869,64 @@ fn main() -> Result<(), Box<dyn Error>> {
             )?;
         }
 
+        if let Some(mode) = options.large_objects {
+            let oids = large_objects::find_large_objects(&mut client, &options, &tables)?;
+            large_objects::write_large_objects(
+                &mut std::io::stdout(),
+                &mut client,
+                &oids,
+                mode,
+                options.truncate || options.incremental.is_some(),
+            )?;
+        }
+
         // Dump table data.
         for table in tables.iter() {
-            let query = table.copy_out_query(&options);
-            // let query = format!("{query} LIMIT 10"); // TESTING ONLY
-            let copy_statement = format!("COPY ({}) TO stdout;", query);
             pb.set_message(table.name.to_owned());
+            match &options.incremental {
+                Some(incremental) => {
+                    let entries = incremental.dump_table(&mut client, table, &options)?;
+                    manifest.tables.extend(entries);
+                }
+                None => {
+                    let entry = write_table(&mut client, table, &options)?;
+                    manifest.tables.push(entry);
+                }
+            }
+            pb.inc(1);
+        }
+
+        if let Some(reset) = options.reset_sequences {
+            let sequences = sequences::find_sequences(&mut client, &options, &tables)?;
+            sequences::write_setvals(
+                &mut std::io::stdout(),
+                &mut client,
+                &options,
+                &sequences,
+                reset,
+            )?;
+        }
 
-            let mut stdout = std::io::stdout();
-            writeln!(stdout, "{};", table.copy_in_query())?;
-            let mut reader = client.copy_out(&copy_statement)?;
-            let size = std::io::copy(&mut reader, &mut stdout)?;
-            sizes.push((table.name.clone(), size));
-            writeln!(stdout, "\\.")?;
+        matviews::write_refreshes(&mut std::io::stdout(), &options, &materialized_views)?;
 
-            pb.inc(1);
+        if let Some(exported_snapshot) = &exported_snapshot {
+            schema::write_schema(
+                &mut std::io::stdout(),
+                &options,
+                &tables,
+                &materialized_views,
+                exported_snapshot,
+                Section::PostData,
+            )?;
         }
 
         // Summarize table sizes. Append the report to the dump as SQL comments.
         {
+            let mut sizes: Vec<(&String, u64)> = manifest
+                .tables
+                .iter()
+                .map(|table| (&table.name, table.bytes))
+                .collect();
             let total = sizes.iter().map(|(.., size)| *size).sum::<u64>();
             if total > 0 {
                 let mut stdout = std::io::stdout();
@@ -301,6 +942,12 @@ fn main() -> Result<(), Box<dyn Error>> {
             }
         }
 
+        writeln!(std::io::stdout())?;
+        manifest.write_tables(&mut std::io::stdout())?;
+        if let Some(path) = &options.manifest {
+            manifest.save(Path::new(path))?;
+        }
+
         pb.finish_with_message(format!("Dumped {} tables", tables.len()));
     }
 
@@ -309,10 +956,131 @@ fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-#[derive(Debug, Clone)]
+/// Write the rows to be restored into `table`, and return their entry for the
+/// manifest.
+fn write_table(
+    client: &mut Client,
+    table: &Table,
+    options: &Options,
+) -> Result<TableManifest, Box<dyn Error>> {
+    let query = table.copy_out_query(options);
+    // let query = format!("{query} LIMIT 10"); // TESTING ONLY
+    if !table.remaps_large_objects(options) {
+        return write_copy_block(
+            client,
+            options,
+            &table.copy_in_query(),
+            table,
+            &table.name,
+            &table.column_names(),
+            query,
+        );
+    }
+
+    // Load into a temporary table of the same name first, and rewrite large
+    // object references on the way from there into the real one.
+    let mut stdout = std::io::stdout();
+    let target = table.sql_identifier();
+    let columns = table.column_list();
+    let copy = format!("pg_temp.{}", table.name.sql_identifier());
+    writeln!(
+        stdout,
+        "CREATE TEMPORARY TABLE {copy} AS SELECT {columns} FROM {target} WITH NO DATA;"
+    )?;
+    let entry = write_copy_block(
+        client,
+        options,
+        &format!("COPY {copy} ({columns}) FROM stdin"),
+        table,
+        &table.name,
+        &table.column_names(),
+        query,
+    )?;
+    writeln!(
+        stdout,
+        "INSERT INTO {target} ({columns}) OVERRIDING SYSTEM VALUE SELECT {} FROM {copy};",
+        table.select_list(options)
+    )?;
+    writeln!(stdout, "DROP TABLE {copy};")?;
+    Ok(entry)
+}
+
+/// Write a `COPY ... FROM stdin` block holding the results of `query`, whose
+/// columns are `columns`, and return its entry for the manifest.
+fn write_copy_block(
+    client: &mut Client,
+    options: &Options,
+    copy_in_query: &str,
+    table: &Table,
+    name: &str,
+    columns: &[String],
+    query: String,
+) -> Result<TableManifest, Box<dyn Error>> {
+    let mut stdout = std::io::stdout();
+    writeln!(stdout, "{copy_in_query};")?;
+    let ids = match &options.leak_scan {
+        Some(scan) => leaks::tenant_ids(client, options, scan, table)?,
+        None => HashMap::new(),
+    };
+    let mut reader = client.copy_out(&format!("COPY ({query}) TO stdout;"))?;
+    let mut scanner = LeakScanner::new(&mut stdout, columns, ids);
+    let mut writer = CopyWriter::new(&mut scanner);
+    std::io::copy(&mut reader, &mut writer)?;
+    let (rows, bytes, sha256) = (writer.rows, writer.bytes, writer.sha256());
+    if scanner.leaks > 0 {
+        let examples: Vec<&String> = scanner.examples.iter().collect();
+        eprintln!(
+            "Leak scan: {name} has {} values from other tenants, such as {examples:?}",
+            scanner.leaks
+        );
+    }
+    let entry = TableManifest {
+        name: name.to_owned(),
+        query,
+        primary_key: table.primary_key.clone(),
+        rows,
+        bytes,
+        sha256,
+        leaks: Some(scanner.leaks).filter(|&leaks| leaks > 0),
+    };
+    writeln!(stdout, "\\.")?;
+    Ok(entry)
+}
+
+/// The condition `--since` adds for every table with `column`.
+fn since_filter(column: String, since: &str) -> ColumnFilter {
+    ColumnFilter {
+        where_clause: format!(
+            "{} >= {}",
+            column.sql_identifier(),
+            since.replace('\'', "''").sql_value()
+        ),
+        column,
+    }
+}
+
+#[derive(Debug, Clone, Default)]
 struct Table {
     name: String,
     columns: Vec<Column>,
+    primary_key: Vec<String>,
+    /// Partitioned tables this table is a partition of, nearest first.
+    ancestors: Vec<String>,
+    /// Every partition below this table, if it is partitioned.
+    partitions: Vec<String>,
+    /// Whether other tables inherit from this one, other than as partitions.
+    inherited: bool,
+    /// Conditions picking out rows that other tables' samples refer to.
+    referenced_rows: Vec<String>,
+    /// The rows dumped from each table `referenced_rows` reads from, by the
+    /// name of a common table expression, with any they read from first.
+    with: Vec<(String, String)>,
+    /// Whether `columns` leaves out any of the table's columns because of
+    /// `exclude_columns`.
+    has_excluded_columns: bool,
+    /// Conditions from `column_filters` for columns this table has.
+    filters: Vec<String>,
+    settings: TableSettings,
     schema: String,
     size: u64, // Bytes.
     rows: u64, // Estimate.
@@ -327,34 +1095,121 @@ impl Table {
         )
     }
     fn copy_out_query(&self, options: &Options) -> String {
-        let column_values = options.column_values.iter().map(|s| s.sql_value());
-        let column_values = intersperse(column_values, ",".to_string()).collect::<String>();
-        if let Some(query) = options.overrides.get(&self.name) {
-            lazy_static! {
-                static ref RE: Regex = Regex::new(r":ids\b").unwrap();
-            }
-            RE.replace_all(query, format!("({column_values})"))
-                .to_string()
-        } else {
-            let query = format!(
-                "SELECT {} FROM {}",
-                &self.column_list(),
-                &self.sql_identifier()
-            );
-            if let Some(scope_column) = self
-                .columns
-                .iter()
-                .find(|column| column.name == options.column_name)
-            {
-                let column_ident = options.column_name.sql_identifier();
-                if scope_column.is_nullable {
-                    format!("{query} WHERE {column_ident} IN ({column_values}) OR {column_ident} IS NULL")
+        let query = self.rows_query(options);
+        if self.with.is_empty() {
+            return query;
+        }
+        let ctes: Vec<String> = self
+            .with
+            .iter()
+            .map(|(name, query)| format!("{name} AS ({query})"))
+            .collect();
+        format!("WITH {} {query}", ctes.join(", "))
+    }
+    /// The query for the rows to dump, reading from the common table
+    /// expressions in `with`.
+    fn rows_query(&self, options: &Options) -> String {
+        let sample = self.sample(options);
+        let sampled = self.source_query(options, options.sampling.tablesample(&sample));
+        let sampled = match sample.limit {
+            Some(limit) => {
+                // Order by something stable, so that the same rows are picked
+                // each time.
+                let order = if let Some(order_by) = &self.settings.order_by {
+                    order_by.clone()
+                } else if self.primary_key.is_empty() {
+                    "limited::text".to_owned()
                 } else {
-                    format!("{query} WHERE {column_ident} IN ({column_values})")
+                    self.primary_key
+                        .iter()
+                        .map(|column| column.sql_identifier())
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                };
+                format!("SELECT * FROM ({sampled}) AS limited ORDER BY {order} LIMIT {limit}")
+            }
+            None => match &self.settings.order_by {
+                Some(order_by) => {
+                    format!("SELECT * FROM ({sampled}) AS ordered ORDER BY {order_by}")
                 }
-            } else {
-                query
+                None => sampled,
+            },
+        };
+        if self.referenced_rows.is_empty() {
+            sampled
+        } else {
+            sampling::with_referenced_rows(
+                sampled,
+                self.source_query(options, None),
+                &self.primary_key,
+                &self.referenced_rows,
+                self.settings.order_by.as_ref(),
+            )
+        }
+    }
+    /// The query for all of this table's rows in scope, or a sample of them.
+    fn source_query(&self, options: &Options, tablesample: Option<String>) -> String {
+        let mut conditions: Vec<String> = self
+            .settings
+            .where_clause
+            .as_ref()
+            .into_iter()
+            .chain(self.filters.iter())
+            .map(|condition| substitute_placeholders(condition, options))
+            .collect();
+        let query = if let Some(query) = &self.settings.query {
+            let query = substitute_placeholders(query, options);
+            if !self.has_excluded_columns && conditions.is_empty() {
+                return query;
             }
+            format!("SELECT {} FROM ({query}) AS projected", self.column_list())
+        } else {
+            let source = match options.view_sources.get(&self.name) {
+                Some(view) => format!("{}.{}", self.schema.sql_identifier(), view.sql_identifier()),
+                // Rows in tables inheriting from this one are dumped with
+                // those tables.
+                None if self.inherited => format!("ONLY {}", self.sql_identifier()),
+                None => self.sql_identifier(),
+            };
+            let source = match tablesample {
+                Some(tablesample) => format!("{source} {tablesample}"),
+                None => source,
+            };
+            conditions.splice(0..0, self.scope_condition(options));
+            format!("SELECT {} FROM {source}", &self.column_list())
+        };
+        match conditions.len() {
+            0 => query,
+            1 => format!("{query} WHERE {}", conditions[0]),
+            _ => format!("{query} WHERE ({})", conditions.join(") AND (")),
+        }
+    }
+    /// The slice of this table to dump.
+    fn sample(&self, options: &Options) -> Sample {
+        options
+            .sampling
+            .sample(&self.settings, self.reads_table(options))
+    }
+    /// Whether this table is dumped from the table itself, rather than through
+    /// an override or a view.
+    fn reads_table(&self, options: &Options) -> bool {
+        self.settings.query.is_none() && !options.view_sources.contains_key(&self.name)
+    }
+    /// The condition restricting this table to the requested `column_values`,
+    /// if it has a `column_name` column.
+    fn scope_condition(&self, options: &Options) -> Option<String> {
+        let scope_column = self
+            .columns
+            .iter()
+            .find(|column| column.name == options.column_name)?;
+        let column_ident = options.column_name.sql_identifier();
+        let column_values = options.sql_column_values();
+        if scope_column.is_nullable {
+            Some(format!(
+                "{column_ident} IN ({column_values}) OR {column_ident} IS NULL"
+            ))
+        } else {
+            Some(format!("{column_ident} IN ({column_values})"))
         }
     }
     fn copy_in_query(&self) -> String {
@@ -365,6 +1220,12 @@ impl Table {
             columns = self.column_list()
         )
     }
+    fn column_names(&self) -> Vec<String> {
+        self.columns
+            .iter()
+            .map(|column| column.name.clone())
+            .collect()
+    }
     fn column_list(&self) -> String {
         self.columns
             .iter()
@@ -372,24 +1233,135 @@ impl Table {
             .collect::<Vec<String>>()
             .join(", ")
     }
+    /// Whether rows must go through a temporary table, so that large object
+    /// references can be rewritten on their way into this table.
+    fn remaps_large_objects(&self, options: &Options) -> bool {
+        options.large_objects == Some(LargeObjects::Remap)
+            && self.columns.iter().any(|column| column.is_oid)
+    }
+    /// `column_list` for selecting from a temporary copy of this table, with
+    /// large object references rewritten if `remaps_large_objects`.
+    fn select_list(&self, options: &Options) -> String {
+        if !self.remaps_large_objects(options) {
+            return self.column_list();
+        }
+        self.columns
+            .iter()
+            .map(|column| {
+                if column.is_oid {
+                    large_objects::remapped(&self.name, &column.name)
+                } else {
+                    column.name.sql_identifier()
+                }
+            })
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
 }
 
 #[derive(Debug, Clone)]
 struct Column {
     pub name: String,
     pub is_nullable: bool,
+    /// Whether the column is an `oid` or `lo`, which may refer to a large
+    /// object.
+    pub is_oid: bool,
 }
 
-fn get_tables(options: &Options) -> Result<Vec<Table>, Box<dyn Error>> {
-    let mut client = pg_client(options)?;
-    let query = format!(
+/// Whether a partition with the given partition constraint could hold rows
+/// for any of the requested IDs, or rows where `column_name` is NULL if it is
+/// `nullable`.
+///
+/// The constraint is evaluated against the IDs themselves. If it involves any
+/// other column, this fails, and the partition is assumed to match.
+fn partition_may_contain(
+    client: &mut Client,
+    options: &Options,
+    constraint: &str,
+    scope_type: &str,
+    nullable: bool,
+) -> bool {
+    client
+        .query_one(
+            &partition_query(options, constraint, scope_type, nullable),
+            &[],
+        )
+        .map(|row| row.get(0))
+        .unwrap_or(true)
+}
+
+/// The query behind `partition_may_contain`.
+fn partition_query(
+    options: &Options,
+    constraint: &str,
+    scope_type: &str,
+    nullable: bool,
+) -> String {
+    let values = options
+        .column_values
+        .iter()
+        .map(|value| format!("({}::{scope_type})", value.sql_value()))
+        .chain(nullable.then(|| format!("(NULL::{scope_type})")))
+        .collect::<Vec<String>>()
+        .join(", ");
+    format!(
+        "SELECT EXISTS (SELECT FROM (VALUES {values}) AS ids ({column}) WHERE {constraint})",
+        column = options.column_name.sql_identifier()
+    )
+}
+
+/// The catalog query behind `get_tables` and `list-tables`, with a row for
+/// every table in the schema.
+///
+/// Partitions are only matched up with their partitioned tables from
+/// PostgreSQL 12, which added `pg_partition_ancestors` and
+/// `pg_partition_tree`. Before that, partitions are dumped like plain tables,
+/// and partitioned tables, which hold no rows of their own, are left out.
+fn tables_query(client: &mut Client, options: &Options) -> Result<String, Box<dyn Error>> {
+    let version: i32 = client
+        .query_one("SELECT current_setting('server_version_num')::int", &[])?
+        .get(0);
+    let partitions = version >= 120000;
+    if !partitions && options.partitioned_tables == PartitionedTables::Parent {
+        return Err("partitioned_tables = \"parent\" needs PostgreSQL 12 or later".into());
+    }
+    Ok(format!(
         r#"
         select
           tables.table_name,
           pg_total_relation_size(pg_class.oid)::text as table_size,
           max(pg_class.reltuples::int8)::text as table_rows, -- https://wiki.postgresql.org/wiki/Count_estimate
           array_agg(columns.column_name::text order by columns.ordinal_position) as column_names,
-          array_agg(columns.is_nullable = 'YES' order by columns.ordinal_position) as column_nullables
+          array_agg(columns.is_nullable = 'YES' order by columns.ordinal_position) as column_nullables,
+          array_agg(columns.udt_name = 'oid' order by columns.ordinal_position) as column_oids,
+          (
+            select coalesce(array_agg(pg_attribute.attname::text order by array_position(pg_index.indkey::int2[], pg_attribute.attnum)), '{{}}')
+            from pg_index
+            join pg_attribute on (
+              pg_attribute.attrelid = pg_index.indrelid
+              and pg_attribute.attnum = any(pg_index.indkey))
+            where pg_index.indrelid = pg_class.oid
+            and pg_index.indisprimary
+          ) as primary_key,
+          {ancestors} as ancestors,
+          {partitions} as partitions,
+          pg_class.relkind = 'p' as partitioned,
+          pg_class.relkind = 'r' and exists (
+            select from pg_inherits where pg_inherits.inhparent = pg_class.oid
+          ) as inherited,
+          {partition_constraint} as partition_constraint,
+          (
+            select format_type(pg_attribute.atttypid, pg_attribute.atttypmod)
+            from pg_attribute
+            where pg_attribute.attrelid = pg_class.oid
+            and pg_attribute.attname = {column_name}
+          ) as scope_type,
+          (
+            select not pg_attribute.attnotnull
+            from pg_attribute
+            where pg_attribute.attrelid = pg_class.oid
+            and pg_attribute.attname = {column_name}
+          ) as scope_nullable
         from information_schema.tables
         join information_schema.columns on (
           columns.table_catalog = tables.table_catalog
@@ -403,43 +1375,458 @@ fn get_tables(options: &Options) -> Result<Vec<Table>, Box<dyn Error>> {
           pg_class.relnamespace = pg_namespace.oid
           and pg_class.relname = tables.table_name)
         where tables.table_schema = {schema}
-        and tables.table_type = 'BASE TABLE'
-        group by tables.table_name, pg_class.oid
+        and tables.table_type in ({table_types}){partitioned_tables}
+        group by tables.table_name, pg_class.oid, pg_class.relkind
         order by tables.table_name
         "#,
         schema = options.schema.sql_value(),
-    );
-    let mut tables: Vec<Table> = client
-        .query(&query, &[])?
-        .into_iter()
-        .filter_map(|row| {
-            let table_name: String = row.get("table_name");
-            if options.skip_tables.is_match(&table_name) {
-                None
-            } else {
-                let table_size_s: String = row.get("table_size");
-                let table_size: u64 = table_size_s.parse().unwrap_or(0);
-                let table_rows_s: String = row.get("table_rows");
-                let table_rows: u64 = table_rows_s.parse().unwrap_or(0);
-                let column_names: Vec<String> = row.get("column_names");
-                let column_nullables: Vec<bool> = row.get("column_nullables");
-                let columns = column_names
-                    .into_iter()
-                    .zip(column_nullables)
-                    .map(|(name, is_nullable)| Column { name, is_nullable })
-                    .collect();
-                Some(Table {
-                    name: table_name,
-                    columns,
-                    schema: options.schema.clone(),
-                    size: table_size,
-                    rows: table_rows,
-                })
+        column_name = options.column_name.sql_value(),
+        table_types = if options.foreign_tables {
+            "'BASE TABLE', 'FOREIGN'"
+        } else {
+            "'BASE TABLE'"
+        },
+        ancestors = if partitions {
+            r#"(
+            select coalesce(array_agg(ancestor.relname::text order by ancestors.level), '{}')
+            from pg_partition_ancestors(pg_class.oid) with ordinality as ancestors(relid, level)
+            join pg_class as ancestor on ancestor.oid = ancestors.relid
+            where ancestors.relid <> pg_class.oid
+          )"#
+        } else {
+            "'{}'::text[]"
+        },
+        partitions = if partitions {
+            r#"(
+            select coalesce(array_agg(partition.relname::text order by partition.relname), '{}')
+            from pg_partition_tree(pg_class.oid) as tree
+            join pg_class as partition on partition.oid = tree.relid
+            where tree.relid <> pg_class.oid
+          )"#
+        } else {
+            "'{}'::text[]"
+        },
+        partition_constraint = if partitions {
+            "pg_get_partition_constraintdef(pg_class.oid)"
+        } else {
+            "null::text"
+        },
+        partitioned_tables = if partitions {
+            ""
+        } else {
+            "\n        and pg_class.relkind <> 'p'"
+        },
+    ))
+}
+
+/// Whether the table in a row of `tables_query` is dumped, and the rule that
+/// decided it.
+fn table_verdict(client: &mut Client, options: &Options, row: &Row) -> Verdict {
+    let table_name: String = row.get("table_name");
+    let ancestors: Vec<String> = row.get("ancestors");
+    let partitioned: bool = row.get("partitioned");
+
+    let verdict = options.verdict(&table_name, &ancestors);
+    if !verdict.included {
+        return verdict;
+    }
+
+    // Dump partitioned tables either through the root or partition by
+    // partition, but not both, or the rows would be dumped twice. A root
+    // with an override is always dumped through the root.
+    let through_root = |root: &String| {
+        options.partitioned_tables == PartitionedTables::Parent
+            || options.tables.settings(root, &[]).query.is_some()
+    };
+    let rule = match (partitioned, ancestors.last()) {
+        (true, None) if !through_root(&table_name) => Some(Rule::Partitions),
+        // Intermediate partitioned tables hold no rows of their own.
+        (true, Some(_)) => Some(Rule::Partitions),
+        (false, Some(root)) if through_root(root) => Some(Rule::ThroughRoot(root.clone())),
+        _ => None,
+    };
+    if let Some(rule) = rule {
+        return Verdict {
+            included: false,
+            rule,
+        };
+    }
+
+    // Skip partitions whose bounds rule out every requested ID.
+    let partition_constraint: Option<String> = row.get("partition_constraint");
+    let scope_type: Option<String> = row.get("scope_type");
+    let nullable: Option<bool> = row.get("scope_nullable");
+    if let (Some(constraint), Some(scope_type)) = (partition_constraint, scope_type) {
+        if options
+            .tables
+            .settings(&table_name, &ancestors)
+            .query
+            .is_none()
+            && !partition_may_contain(
+                client,
+                options,
+                &constraint,
+                &scope_type,
+                nullable.unwrap_or(true),
+            )
+        {
+            return Verdict {
+                included: false,
+                rule: Rule::PartitionBounds,
+            };
+        }
+    }
+    verdict
+}
+
+fn get_tables(options: &Options) -> Result<Vec<Table>, Box<dyn Error>> {
+    let mut client = pg_client(options)?;
+    let query = tables_query(&mut client, options)?;
+    let mut tables: Vec<Table> = Vec::new();
+    for row in client.query(&query, &[])?.into_iter() {
+        if !table_verdict(&mut client, options, &row).included {
+            continue;
+        }
+        let table_name: String = row.get("table_name");
+        let ancestors: Vec<String> = row.get("ancestors");
+        let settings = options.tables.settings(&table_name, &ancestors);
+
+        let table_size_s: String = row.get("table_size");
+        let table_size: u64 = table_size_s.parse().unwrap_or(0);
+        let table_rows_s: String = row.get("table_rows");
+        let table_rows: u64 = table_rows_s.parse().unwrap_or(0);
+        let column_names: Vec<String> = row.get("column_names");
+        let column_nullables: Vec<bool> = row.get("column_nullables");
+        let column_oids: Vec<bool> = row.get("column_oids");
+        let primary_key: Vec<String> = row.get("primary_key");
+        let column_count = column_names.len();
+        let filters = columns::filter_conditions(&options.column_filters, &column_names);
+        if let Some(unknown) = settings
+            .columns
+            .iter()
+            .flatten()
+            .find(|name| !column_names.contains(name))
+        {
+            return Err(
+                format!("columns for {table_name} names {unknown}, which it doesn't have").into(),
+            );
+        }
+        let mut columns = Vec::with_capacity(column_names.len());
+        for ((name, is_nullable), is_oid) in column_names
+            .into_iter()
+            .zip(column_nullables)
+            .zip(column_oids)
+        {
+            let left_out = settings
+                .columns
+                .as_ref()
+                .is_some_and(|columns| !columns.contains(&name));
+            if left_out
+                || options
+                    .exclude_columns
+                    .is_excluded(&table_name, &ancestors, &name)
+            {
+                // Without these, rows can't be scoped or matched up.
+                if name == options.column_name || primary_key.contains(&name) {
+                    return Err(format!(
+                        "Can't leave out {table_name}.{name}, which is used to select or match rows"
+                    )
+                    .into());
+                }
+                continue;
             }
+            columns.push(Column {
+                name,
+                is_nullable,
+                is_oid,
+            });
+        }
+        let has_excluded_columns = columns.len() < column_count;
+        tables.push(Table {
+            name: table_name,
+            has_excluded_columns,
+            filters,
+            settings,
+            columns,
+            primary_key,
+            ancestors,
+            partitions: row.get("partitions"),
+            inherited: row.get("inherited"),
+            referenced_rows: Vec::new(),
+            with: Vec::new(),
+            schema: options.schema.clone(),
+            size: table_size,
+            rows: table_rows,
+        });
+    }
+
+    tables.sort_by(|a, b| a.name.cmp(&b.name));
+
+    check_view_sources(&mut client, options, &tables)?;
+    if options.strict {
+        check_scoped(options, &tables)?;
+    }
+    overrides_dir::report_orphans(&mut client, options)?;
+    if let Some(table) = tables
+        .iter()
+        .find(|table| table.settings.sample_percent.is_some() && !table.reads_table(options))
+    {
+        return Err(format!(
+            "{} has a sample_percent, but is dumped through an override or a view, which can't be sampled",
+            table.name
+        )
+        .into());
+    }
+    if options.sampling.references {
+        sampling::add_referenced_rows(&mut client, options, &mut tables)?;
+    }
+
+    selected::dump_order(tables)
+}
+
+/// Make sure every table is either scoped by `column_name`, dumped with an
+/// override, or listed in `global_tables`. Skipped tables never get here.
+fn check_scoped(options: &Options, tables: &[Table]) -> Result<(), Box<dyn Error>> {
+    let unscoped: Vec<&str> = tables
+        .iter()
+        .filter(|table| {
+            table.scope_condition(options).is_none()
+                && table.settings.query.is_none()
+                && !options.is_global(&table.name, &table.ancestors)
         })
+        .map(|table| table.name.as_str())
         .collect();
+    if unscoped.is_empty() {
+        return Ok(());
+    }
+    Err(format!(
+        "Strict mode: {} would be dumped in full, without {}. Give each an override, skip it, or list it in global_tables.",
+        unscoped.join(", "),
+        options.column_name
+    )
+    .into())
+}
 
-    tables.sort_by(|a, b| a.name.cmp(&b.name));
+/// Make sure each view in `view_sources` exists and has every column of the
+/// table it is dumped into.
+fn check_view_sources(
+    client: &mut Client,
+    options: &Options,
+    tables: &[Table],
+) -> Result<(), Box<dyn Error>> {
+    for (table_name, view) in options.view_sources.iter() {
+        if options.tables.settings(table_name, &[]).query.is_some() {
+            return Err(format!("{table_name} has both an override and a view source").into());
+        }
+        let Some(table) = tables.iter().find(|table| &table.name == table_name) else {
+            if !options.verdict(table_name, &[]).included {
+                continue;
+            }
+            return Err(format!(
+                "View source {view} targets {table_name}, which is not a table in {}",
+                options.schema
+            )
+            .into());
+        };
+        let view_columns: Vec<String> = client
+            .query(
+                "select attname::text from pg_attribute where attrelid = to_regclass($1) and attnum > 0 and not attisdropped",
+                &[&format!("{}.{}", options.schema.sql_identifier(), view.sql_identifier())],
+            )?
+            .into_iter()
+            .map(|row| row.get(0))
+            .collect();
+        if view_columns.is_empty() {
+            return Err(format!(
+                "View source {view} for {table_name} not found in {}",
+                options.schema
+            )
+            .into());
+        }
+        let missing = missing_view_columns(table, &view_columns);
+        if !missing.is_empty() {
+            return Err(format!(
+                "View source {view} for {table_name} is missing columns: {}",
+                missing.join(", ")
+            )
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// The columns of `table` that a view with `view_columns` doesn't have,
+/// including `column_name`, without which strict mode would dump the view's
+/// rows unscoped.
+fn missing_view_columns<'a>(table: &'a Table, view_columns: &[String]) -> Vec<&'a str> {
+    table
+        .columns
+        .iter()
+        .map(|column| column.name.as_str())
+        .filter(|column| !view_columns.iter().any(|c| c == column))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn it_refuses_unscoped_tables_in_strict_mode() {
+        let file: InputFile = toml::from_str(
+            r#"
+            column_name = "customer_id"
+            schema_name = "public"
+            skip_tables = ["^obsolete_"]
+            [overrides]
+            user_files = "select * from user_files"
+            "#,
+        )
+        .unwrap();
+        let options = Options {
+            column_name: "customer_id".to_owned(),
+            strict: true,
+            global_tables: vec![Regex::new("^countries$").unwrap()],
+            tables: TableConfigs::load(&file, &[], &Snippets::default()).unwrap(),
+            view_sources: HashMap::from([("users".to_owned(), "users_export".to_owned())]),
+            ..Options::default()
+        };
+        let table = |name: &str, columns: &[&str]| Table {
+            name: name.to_owned(),
+            schema: "public".to_owned(),
+            columns: columns
+                .iter()
+                .map(|column| Column {
+                    name: column.to_string(),
+                    is_nullable: false,
+                    is_oid: false,
+                })
+                .collect(),
+            settings: options.tables.settings(name, &[]),
+            ..Table::default()
+        };
+        let users = table("users", &["id", "customer_id"]);
+        let mut tables = vec![
+            table("countries", &["code"]),
+            table("user_files", &["id", "user_id"]),
+            users.clone(),
+        ];
+        assert!(check_scoped(&options, &tables).is_ok());
+
+        // Skipped tables are never dumped, so they aren't checked.
+        assert!(!options.verdict("obsolete_logs", &[]).included);
+
+        tables.push(table("audit_log", &["id", "message"]));
+        let err = check_scoped(&options, &tables).unwrap_err().to_string();
+        assert!(err.starts_with("Strict mode: audit_log would be dumped in full"));
+
+        // Users are scoped through their view only if it has customer_id.
+        let view_columns = vec!["id".to_owned()];
+        assert_eq!(
+            missing_view_columns(&users, &view_columns),
+            vec!["customer_id"]
+        );
+    }
+
+    #[test]
+    fn it_keeps_where_clauses_apart_from_the_scope_condition() {
+        let options = Options {
+            column_name: "customer_id".to_owned(),
+            column_values: vec!["acme".to_owned()],
+            ..Options::default()
+        };
+        let table = Table {
+            name: "documents".to_owned(),
+            schema: "public".to_owned(),
+            columns: vec![Column {
+                name: "customer_id".to_owned(),
+                is_nullable: true,
+                is_oid: false,
+            }],
+            settings: TableSettings {
+                where_clause: Some("published or draft".to_owned()),
+                ..TableSettings::default()
+            },
+            ..Table::default()
+        };
+        assert_eq!(
+            table.source_query(&options, None),
+            r#"SELECT "customer_id" FROM "public".documents WHERE ("customer_id" IN ('acme') OR "customer_id" IS NULL) AND (published or draft)"#
+        );
+    }
 
-    Ok(tables)
+    #[test]
+    fn it_only_looks_for_null_ids_in_nullable_columns() {
+        let options = Options {
+            column_name: "customer_id".to_owned(),
+            column_values: vec!["acme".to_owned()],
+            ..Options::default()
+        };
+        // A DEFAULT partition's constraint, which NULL always satisfies.
+        let constraint =
+            "(NOT ((customer_id IS NOT NULL) AND (customer_id = ANY (ARRAY['acme'::text]))))";
+        assert_eq!(
+            partition_query(&options, constraint, "text", true),
+            format!(
+                r#"SELECT EXISTS (SELECT FROM (VALUES ('acme'::text), (NULL::text)) AS ids ("customer_id") WHERE {constraint})"#
+            )
+        );
+        assert_eq!(
+            partition_query(&options, constraint, "text", false),
+            format!(
+                r#"SELECT EXISTS (SELECT FROM (VALUES ('acme'::text)) AS ids ("customer_id") WHERE {constraint})"#
+            )
+        );
+    }
+
+    #[test]
+    fn it_escapes_since() {
+        assert_eq!(
+            since_filter("updated_at".to_owned(), "2024-01-01' OR true --").where_clause,
+            r#""updated_at" >= '2024-01-01'' OR true --'"#
+        );
+    }
+
+    #[test]
+    fn it_substitutes_placeholders_in_column_filters() {
+        let options = Options {
+            column_name: "customer_id".to_owned(),
+            column_values: vec!["acme".to_owned()],
+            ..Options::default()
+        };
+        let table = Table {
+            name: "invoices".to_owned(),
+            schema: "public".to_owned(),
+            columns: vec![Column {
+                name: "billing_customer_id".to_owned(),
+                is_nullable: false,
+                is_oid: false,
+            }],
+            filters: vec!["billing_customer_id IN :ids".to_owned()],
+            ..Table::default()
+        };
+        assert_eq!(
+            table.copy_out_query(&options),
+            r#"SELECT "billing_customer_id" FROM "public".invoices WHERE billing_customer_id IN ('acme')"#
+        );
+    }
+
+    #[test]
+    fn it_substitutes_selected_placeholders_in_column_filters() {
+        let table = Table {
+            name: "comments".to_owned(),
+            schema: "public".to_owned(),
+            columns: vec![Column {
+                name: "author_id".to_owned(),
+                is_nullable: true,
+                is_oid: false,
+            }],
+            filters: vec!["author_id IN :selected(users, id)".to_owned()],
+            ..Table::default()
+        };
+        assert_eq!(
+            table.copy_out_query(&Options::default()),
+            r#"SELECT "author_id" FROM "public"."comments" WHERE author_id IN (SELECT "id" FROM "pg_parcel_selected_users")"#
+        );
+    }
 }
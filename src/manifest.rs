@@ -0,0 +1,154 @@
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+/// First line of the comment block at the top of a parcel.
+pub const HEADER_START: &str = "-- PARCEL ----------------------------------";
+/// First line of the comment block at the end of a parcel.
+pub const TABLES_START: &str = "-- MANIFEST --------------------------------";
+/// Last line of either block.
+pub const BLOCK_END: &str = "-- -----------------------------------------";
+
+/// A record of how a parcel was made.
+///
+/// The parcel itself carries this as TOML inside SQL comments: everything
+/// known up front goes in a header block, and `tables`, which is only known
+/// once the data has been written, goes in a block at the end. The same
+/// content can also be written to a sidecar file with `--manifest`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Manifest {
+    pub pg_parcel_version: String,
+    pub server_version: String,
+    pub schema: String,
+    pub column_name: String,
+    pub ids: Vec<String>,
+    pub features: Vec<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub params: BTreeMap<String, String>,
+    /// Start of the dump transaction, in UTC.
+    pub snapshot: String,
+    /// Rows changed at or after this time, in UTC, may be missing from the
+    /// parcel: the start of the oldest transaction still open when the
+    /// snapshot was taken, or `snapshot` if there was none.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub watermark: Option<String>,
+    /// The snapshot as `pg_current_snapshot()`, on PostgreSQL 13 and later.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pg_snapshot: Option<String>,
+    /// For a delta parcel, the watermark of the parcel it follows on from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub since: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tables: Vec<TableManifest>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TableManifest {
+    pub name: String,
+    pub query: String,
+    #[serde(default)]
+    pub primary_key: Vec<String>,
+    pub rows: u64,
+    pub bytes: u64,
+    /// SHA-256 of the table's `COPY` data, in hex.
+    pub sha256: String,
+    /// Values from other tenants found by `--scan-leaks`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub leaks: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct Tables<'a> {
+    tables: &'a [TableManifest],
+}
+
+impl Manifest {
+    /// Write everything except `tables` as a comment block.
+    pub fn write_header(&self, out: &mut impl Write) -> Result<(), Box<dyn Error>> {
+        let header = Manifest {
+            tables: Vec::new(),
+            ..self.clone()
+        };
+        write_block(out, HEADER_START, &toml::to_string(&header)?)
+    }
+
+    /// Write `tables` as a comment block.
+    pub fn write_tables(&self, out: &mut impl Write) -> Result<(), Box<dyn Error>> {
+        let tables = Tables {
+            tables: &self.tables,
+        };
+        write_block(out, TABLES_START, &toml::to_string(&tables)?)
+    }
+
+    pub fn load(path: &Path) -> Result<Manifest, Box<dyn Error>> {
+        Ok(toml::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+fn write_block(out: &mut impl Write, start: &str, toml: &str) -> Result<(), Box<dyn Error>> {
+    writeln!(out, "{start}")?;
+    for line in toml.lines() {
+        if line.is_empty() {
+            writeln!(out, "--")?;
+        } else {
+            writeln!(out, "-- {line}")?;
+        }
+    }
+    writeln!(out, "{BLOCK_END}")?;
+    Ok(())
+}
+
+/// Passes `COPY` data through to another writer, keeping track of its size,
+/// row count and checksum along the way.
+pub struct CopyWriter<W: Write> {
+    inner: W,
+    hasher: Sha256,
+    pub bytes: u64,
+    pub rows: u64,
+}
+
+impl<W: Write> CopyWriter<W> {
+    pub fn new(inner: W) -> Self {
+        CopyWriter {
+            inner,
+            hasher: Sha256::new(),
+            bytes: 0,
+            rows: 0,
+        }
+    }
+
+    pub fn sha256(&self) -> String {
+        hex(&self.hasher.clone().finalize())
+    }
+}
+
+impl<W: Write> Write for CopyWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        let buf = &buf[..written];
+        self.hasher.update(buf);
+        self.bytes += written as u64;
+        // Newlines inside values are escaped in `COPY` text format, so every
+        // newline ends a row.
+        self.rows += buf.iter().filter(|&&byte| byte == b'\n').count() as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
@@ -0,0 +1,118 @@
+use crate::sql_string::SqlString;
+use crate::Options;
+use postgres::Client;
+use std::error::Error;
+use std::io::Write;
+
+/// A materialized view to refresh once the parcel's data is loaded.
+#[derive(Debug)]
+pub struct MaterializedView {
+    pub name: String,
+    /// Other materialized views this one selects from.
+    pub dependencies: Vec<String>,
+}
+
+/// Find the materialized views in the schema that are selected, in an order
+/// they can be refreshed in.
+pub fn find_materialized_views(
+    client: &mut Client,
+    options: &Options,
+) -> Result<Vec<MaterializedView>, Box<dyn Error>> {
+    let query = format!(
+        r#"
+        select
+          view.relname::text as name,
+          coalesce(
+            array_agg(distinct dependency.relname::text) filter (where dependency.oid is not null),
+            '{{}}'
+          ) as dependencies
+        from pg_class view
+        join pg_namespace on pg_namespace.oid = view.relnamespace
+        left join pg_rewrite on pg_rewrite.ev_class = view.oid
+        left join pg_depend on (
+          pg_depend.classid = 'pg_rewrite'::regclass
+          and pg_depend.objid = pg_rewrite.oid
+          and pg_depend.refclassid = 'pg_class'::regclass)
+        left join pg_class dependency on (
+          dependency.oid = pg_depend.refobjid
+          and dependency.oid <> view.oid
+          and dependency.relkind = 'm'
+          and dependency.relnamespace = view.relnamespace)
+        where view.relkind = 'm'
+        and pg_namespace.nspname = {schema}
+        group by view.relname
+        order by view.relname
+        "#,
+        schema = options.schema.sql_value(),
+    );
+    let views = client
+        .query(&query, &[])?
+        .into_iter()
+        .map(|row| MaterializedView {
+            name: row.get("name"),
+            dependencies: row.get("dependencies"),
+        })
+        .filter(|view| options.verdict(&view.name, &[]).included)
+        .collect();
+    Ok(refresh_order(views))
+}
+
+/// Sort `views` so that every view comes after the views it depends on,
+/// otherwise keeping their order.
+fn refresh_order(mut views: Vec<MaterializedView>) -> Vec<MaterializedView> {
+    let mut ordered: Vec<MaterializedView> = Vec::with_capacity(views.len());
+    while !views.is_empty() {
+        let ready = views
+            .iter()
+            .position(|view| {
+                view.dependencies.iter().all(|dependency| {
+                    ordered.iter().any(|done| &done.name == dependency)
+                        || !views.iter().any(|view| &view.name == dependency)
+                })
+            })
+            // A dependency cycle can't be created, but don't loop forever.
+            .unwrap_or(0);
+        ordered.push(views.remove(ready));
+    }
+    ordered
+}
+
+/// Write a `REFRESH MATERIALIZED VIEW` statement for each of `views`.
+pub fn write_refreshes(
+    out: &mut impl Write,
+    options: &Options,
+    views: &[MaterializedView],
+) -> Result<(), Box<dyn Error>> {
+    for view in views.iter() {
+        writeln!(
+            out,
+            "REFRESH MATERIALIZED VIEW {}.{};",
+            options.schema.sql_identifier(),
+            view.name.sql_identifier()
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::matviews::*;
+
+    fn view(name: &str, dependencies: &[&str]) -> MaterializedView {
+        MaterializedView {
+            name: name.to_owned(),
+            dependencies: dependencies.iter().map(|d| d.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn it_refreshes_dependencies_first() {
+        let views = vec![
+            view("a", &["c"]),
+            view("b", &[]),
+            view("c", &["b", "skipped"]),
+        ];
+        let names: Vec<String> = refresh_order(views).into_iter().map(|v| v.name).collect();
+        assert_eq!(names, vec!["b", "c", "a"]);
+    }
+}
@@ -0,0 +1,74 @@
+use crate::sql_string::SqlString;
+use crate::Options;
+use postgres::Client;
+use std::error::Error;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// An override read from `<table>.sql` in `overrides_dir`.
+#[derive(Debug, Clone)]
+pub struct OverrideFile {
+    pub table: String,
+    pub path: PathBuf,
+    pub query: String,
+}
+
+/// Read every `.sql` file in `dir`, in name order. Other files are ignored.
+pub fn load(dir: &Path) -> Result<Vec<OverrideFile>, Box<dyn Error>> {
+    let entries = fs::read_dir(dir)
+        .map_err(|e| format!("overrides_dir: can't read {}: {e}", dir.display()))?;
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if !path.is_file() || path.extension() != Some(OsStr::new("sql")) {
+            continue;
+        }
+        let Some(table) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        let query = fs::read_to_string(&path)?;
+        files.push(OverrideFile {
+            table: table.to_owned(),
+            // A file linted as a statement ends with a semicolon, which can't
+            // go inside `COPY (...)`.
+            query: query.trim_end().trim_end_matches(';').to_owned(),
+            path,
+        });
+    }
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(files)
+}
+
+/// Warn about files in `overrides_dir` named after tables that aren't in the
+/// schema, which are most likely left over from a renamed or dropped table.
+pub fn report_orphans(client: &mut Client, options: &Options) -> Result<(), Box<dyn Error>> {
+    if options.override_files.is_empty() {
+        return Ok(());
+    }
+    let query = format!(
+        r#"
+        select pg_class.relname::text
+        from pg_class
+        join pg_namespace on pg_namespace.oid = pg_class.relnamespace
+        where pg_namespace.nspname = {schema}
+        and pg_class.relkind in ('r', 'p', 'f')
+        "#,
+        schema = options.schema.sql_value(),
+    );
+    let tables: Vec<String> = client
+        .query(&query, &[])?
+        .into_iter()
+        .map(|row| row.get(0))
+        .collect();
+    for file in options.override_files.iter() {
+        if !tables.contains(&file.table) {
+            eprintln!(
+                "Warning: {} matches no table in {}",
+                file.path.display(),
+                options.schema
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,200 @@
+use crate::sql_string::SqlString;
+use crate::suggestion::did_you_mean;
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::str::FromStr;
+use toml::value::Datetime;
+use toml::Value;
+
+lazy_static! {
+    static ref NAME: Regex = Regex::new("^[a-z_][a-z0-9_]*$").unwrap();
+    /// A `:name` placeholder, but not the `::name` of a cast.
+    static ref PLACEHOLDER: Regex = Regex::new(r"(^|[^:]):([a-z_][a-z0-9_]*)\b").unwrap();
+}
+
+/// Parameters declared in the `[params]` section of the config file, with
+/// values from `--param` or their defaults.
+///
+/// Each default's TOML type is the parameter's type: a string, integer,
+/// float, boolean, or date or time. Values given on the command line are
+/// parsed as that type.
+#[derive(Debug, Default)]
+pub struct Params {
+    values: BTreeMap<String, Value>,
+}
+
+impl Params {
+    pub fn new(defaults: BTreeMap<String, Value>) -> Result<Params, Box<dyn Error>> {
+        for (name, default) in defaults.iter() {
+            if !NAME.is_match(name) || name == "ids" || name == "selected" {
+                return Err(format!(
+                    "params: {name:?} can't be used as a parameter name; use lower case letters, digits and underscores, and not `ids` or `selected`"
+                )
+                .into());
+            }
+            if matches!(default, Value::Array(_) | Value::Table(_)) {
+                return Err(format!(
+                    "params: {name} must default to a string, number, boolean, or date or time"
+                )
+                .into());
+            }
+        }
+        Ok(Params { values: defaults })
+    }
+
+    /// Set parameters from `name=value` arguments.
+    pub fn set(&mut self, args: &[String]) -> Result<(), Box<dyn Error>> {
+        for arg in args.iter() {
+            let (name, value) = arg
+                .split_once('=')
+                .ok_or_else(|| format!("--param: expected name=value, not {arg:?}"))?;
+            let Some(default) = self.values.get(name) else {
+                let names: Vec<&String> = self.values.keys().collect();
+                let suggestion = did_you_mean(&names, name);
+                return Err(format!(
+                    "--param: no parameter named `{name}` defined in [params].{suggestion}"
+                )
+                .into());
+            };
+            let value = parse(default, value).ok_or_else(|| {
+                format!(
+                    "--param: {name} must be a {}, not {value:?}",
+                    default.type_str()
+                )
+            })?;
+            self.values.insert(name.to_owned(), value);
+        }
+        Ok(())
+    }
+
+    /// Each parameter's name, and its value as text, for session variables and
+    /// the manifest.
+    pub fn texts(&self) -> BTreeMap<String, String> {
+        self.values
+            .iter()
+            .map(|(name, value)| (name.clone(), text(value)))
+            .collect()
+    }
+
+    /// Replace each `:name` placeholder in `query` with the parameter's value,
+    /// as an SQL literal of its type. Casts such as `::text` are left alone.
+    pub fn substitute(&self, query: &str) -> String {
+        PLACEHOLDER
+            .replace_all(query, |captures: &Captures| {
+                match self.values.get(&captures[2]) {
+                    Some(value) => format!("{}{}", &captures[1], literal(value)),
+                    None => captures[0].to_owned(),
+                }
+            })
+            .into_owned()
+    }
+}
+
+/// Parse `value` as the same type as `default`.
+fn parse(default: &Value, value: &str) -> Option<Value> {
+    match default {
+        Value::String(_) => Some(Value::String(value.to_owned())),
+        Value::Integer(_) => value.parse().ok().map(Value::Integer),
+        Value::Float(_) => value.parse().ok().map(Value::Float),
+        Value::Boolean(_) => value.parse().ok().map(Value::Boolean),
+        Value::Datetime(default) => Datetime::from_str(value)
+            .ok()
+            // A date must stay a date, a time a time, and so on.
+            .filter(|datetime| {
+                (
+                    datetime.date.is_some(),
+                    datetime.time.is_some(),
+                    datetime.offset.is_some(),
+                ) == (
+                    default.date.is_some(),
+                    default.time.is_some(),
+                    default.offset.is_some(),
+                )
+            })
+            .map(Value::Datetime),
+        Value::Array(_) | Value::Table(_) => None,
+    }
+}
+
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(string) => string.clone(),
+        Value::Integer(integer) => integer.to_string(),
+        Value::Float(float) => float.to_string(),
+        Value::Boolean(boolean) => boolean.to_string(),
+        Value::Datetime(datetime) => datetime.to_string(),
+        Value::Array(_) | Value::Table(_) => unreachable!(),
+    }
+}
+
+fn literal(value: &Value) -> String {
+    let quoted = text(value).replace('\'', "''").sql_value();
+    match value {
+        Value::String(_) => quoted,
+        Value::Float(float) if float.is_nan() => "'NaN'::float8".to_owned(),
+        Value::Float(float) if float.is_infinite() => {
+            format!("'{}Infinity'::float8", if *float < 0.0 { "-" } else { "" })
+        }
+        // In parentheses, so that `x-:n` doesn't become the comment `x--1`.
+        Value::Integer(integer) if *integer < 0 => format!("({integer})"),
+        Value::Float(float) if *float < 0.0 => format!("({float})"),
+        Value::Integer(_) | Value::Float(_) | Value::Boolean(_) => text(value),
+        Value::Datetime(datetime) => {
+            let cast = match (datetime.date, datetime.time, datetime.offset) {
+                (Some(_), None, _) => "date",
+                (None, _, _) => "time",
+                (Some(_), Some(_), None) => "timestamp",
+                (Some(_), Some(_), Some(_)) => "timestamptz",
+            };
+            format!("{quoted}::{cast}")
+        }
+        Value::Array(_) | Value::Table(_) => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::params::*;
+
+    fn params(defaults: &str, args: &[&str]) -> Result<Params, Box<dyn Error>> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let mut params = Params::new(toml::from_str(defaults)?)?;
+        params.set(&args)?;
+        Ok(params)
+    }
+
+    #[test]
+    fn it_substitutes_typed_values() {
+        let params = params(
+            "since = 2024-01-01\nlimit = 10\nstatus = 'open'",
+            &["since=2025-06-30", "status=it's"],
+        )
+        .unwrap();
+        assert_eq!(
+            params.substitute("select :status::text where at >= :since limit :limit"),
+            "select 'it''s'::text where at >= '2025-06-30'::date limit 10"
+        );
+        assert_eq!(params.texts()["since"], "2025-06-30");
+    }
+
+    #[test]
+    fn it_keeps_numbers_out_of_comments() {
+        let negative = params("n = 1\nx = 1.5", &["n=-1", "x=-inf"]).unwrap();
+        assert_eq!(
+            negative.substitute("select 2-:n, :x, :nx"),
+            "select 2-(-1), '-Infinity'::float8, :nx"
+        );
+        let nan = params("x = 1.5", &["x=NaN"]).unwrap();
+        assert_eq!(nan.substitute(":x"), "'NaN'::float8");
+    }
+
+    #[test]
+    fn it_rejects_bad_values() {
+        assert!(params("limit = 10", &["limit=ten"]).is_err());
+        assert!(params("since = 2024-01-01", &["since=2024-01-01T00:00:00Z"]).is_err());
+        assert!(params("limit = 10", &["limt=5"]).is_err());
+        assert!(params("ids = 'x'", &[]).is_err());
+    }
+}
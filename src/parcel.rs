@@ -0,0 +1,300 @@
+use crate::manifest::{CopyWriter, Manifest, BLOCK_END, HEADER_START, TABLES_START};
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Sink, Write};
+use std::path::Path;
+
+/// A parcel read back from disk.
+#[derive(Debug, Default)]
+pub struct Parcel {
+    /// The manifest embedded in the parcel's comments, if there is one.
+    pub manifest: Option<Manifest>,
+    pub blocks: Vec<CopyBlock>,
+}
+
+/// One `COPY ... FROM stdin` block.
+#[derive(Debug)]
+pub struct CopyBlock {
+    pub name: String,
+    pub columns: Vec<String>,
+    /// The block's data lines, if the parcel was read with `keep_lines`.
+    pub lines: Vec<String>,
+    pub rows: u64,
+    pub bytes: u64,
+    pub sha256: String,
+    /// False if the parcel ended before the block's `\.` terminator.
+    pub complete: bool,
+}
+
+impl CopyBlock {
+    fn finish(self, writer: CopyWriter<Sink>, complete: bool) -> CopyBlock {
+        CopyBlock {
+            rows: writer.rows,
+            bytes: writer.bytes,
+            sha256: writer.sha256(),
+            complete,
+            ..self
+        }
+    }
+}
+
+enum State {
+    Statements,
+    Manifest,
+    Copy,
+}
+
+impl Parcel {
+    pub fn load(path: &Path, keep_lines: bool) -> Result<Parcel, Box<dyn Error>> {
+        Parcel::read(BufReader::new(File::open(path)?), keep_lines)
+    }
+
+    pub fn read(reader: impl BufRead, keep_lines: bool) -> Result<Parcel, Box<dyn Error>> {
+        let mut blocks = Vec::new();
+        let mut manifest_toml = String::new();
+        let mut state = State::Statements;
+        let mut current: Option<(CopyBlock, CopyWriter<Sink>)> = None;
+
+        for line in reader.split(b'\n') {
+            let line = line?;
+            match state {
+                State::Copy if line == b"\\." => {
+                    if let Some((block, writer)) = current.take() {
+                        blocks.push(block.finish(writer, true));
+                    }
+                    state = State::Statements;
+                }
+                State::Copy => {
+                    if let Some((block, writer)) = current.as_mut() {
+                        writer.write_all(&line)?;
+                        writer.write_all(b"\n")?;
+                        if keep_lines {
+                            block
+                                .lines
+                                .push(String::from_utf8_lossy(&line).into_owned());
+                        }
+                    }
+                }
+                State::Manifest if line == BLOCK_END.as_bytes() => {
+                    state = State::Statements;
+                }
+                State::Manifest => {
+                    let line = String::from_utf8(line)?;
+                    let line = line.strip_prefix("--").unwrap_or(&line);
+                    manifest_toml.push_str(line.strip_prefix(' ').unwrap_or(line));
+                    manifest_toml.push('\n');
+                }
+                State::Statements => {
+                    if line == HEADER_START.as_bytes() || line == TABLES_START.as_bytes() {
+                        state = State::Manifest;
+                    } else if let Some((_, name, columns)) =
+                        std::str::from_utf8(&line).ok().and_then(parse_copy_in)
+                    {
+                        let block = CopyBlock {
+                            name,
+                            columns,
+                            lines: Vec::new(),
+                            rows: 0,
+                            bytes: 0,
+                            sha256: String::new(),
+                            complete: false,
+                        };
+                        current = Some((block, CopyWriter::new(io::sink())));
+                        state = State::Copy;
+                    }
+                }
+            }
+        }
+
+        if let Some((block, writer)) = current.take() {
+            blocks.push(block.finish(writer, false));
+        }
+
+        let manifest = if manifest_toml.is_empty() {
+            None
+        } else {
+            Some(toml::from_str(&manifest_toml)?)
+        };
+
+        Ok(Parcel { manifest, blocks })
+    }
+}
+
+/// Split one line of `COPY` text format into its fields, undoing backslash
+/// escapes. SQL `NULL` becomes `None`.
+pub fn split_row(line: &str) -> Vec<Option<String>> {
+    line.split('\t')
+        .map(|field| {
+            if field == "\\N" {
+                None
+            } else {
+                Some(unescape(field))
+            }
+        })
+        .collect()
+}
+
+/// See https://www.postgresql.org/docs/current/sql-copy.html, "Text Format".
+fn unescape(field: &str) -> String {
+    if !field.contains('\\') {
+        return field.to_owned();
+    }
+    let input = field.as_bytes();
+    let mut bytes = Vec::with_capacity(input.len());
+    let mut index = 0;
+    while index < input.len() {
+        let byte = input[index];
+        index += 1;
+        if byte != b'\\' || index == input.len() {
+            bytes.push(byte);
+            continue;
+        }
+        let escaped = input[index];
+        index += 1;
+        match escaped {
+            b'b' => bytes.push(0x08),
+            b'f' => bytes.push(0x0c),
+            b'n' => bytes.push(b'\n'),
+            b'r' => bytes.push(b'\r'),
+            b't' => bytes.push(b'\t'),
+            b'v' => bytes.push(0x0b),
+            b'0'..=b'7' | b'x' => {
+                // Up to three octal digits, or `x` and up to two hex digits.
+                let (radix, start, max_digits) = if escaped == b'x' {
+                    (16, index, 2)
+                } else {
+                    (8, index - 1, 3)
+                };
+                let digits = input[start..]
+                    .iter()
+                    .take(max_digits)
+                    .take_while(|&&digit| (digit as char).is_digit(radix))
+                    .count();
+                match std::str::from_utf8(&input[start..start + digits])
+                    .ok()
+                    .and_then(|digits| u8::from_str_radix(digits, radix).ok())
+                {
+                    Some(value) => {
+                        bytes.push(value);
+                        index = start + digits;
+                    }
+                    None => bytes.push(escaped),
+                }
+            }
+            other => bytes.push(other),
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Parse a statement like `COPY "public".users (id, "name") FROM stdin;` into
+/// its schema, table name and column names.
+fn parse_copy_in(line: &str) -> Option<(String, String, Vec<String>)> {
+    let rest = line.strip_prefix("COPY ")?.strip_suffix(" FROM stdin;")?;
+    let (schema, rest) = parse_identifier(rest)?;
+    let (name, rest) = parse_identifier(rest.strip_prefix('.')?)?;
+    let mut rest = rest.strip_prefix(" (")?;
+    let mut columns = Vec::new();
+    loop {
+        let (column, after) = parse_identifier(rest)?;
+        columns.push(column);
+        if let Some(after) = after.strip_prefix(", ") {
+            rest = after;
+        } else {
+            after.strip_prefix(')').filter(|after| after.is_empty())?;
+            break;
+        }
+    }
+    Some((schema, name, columns))
+}
+
+/// Parse one identifier, quoted or not, from the start of `input`. Returns the
+/// unquoted identifier and whatever follows it.
+fn parse_identifier(input: &str) -> Option<(String, &str)> {
+    if let Some(quoted) = input.strip_prefix('"') {
+        let mut identifier = String::new();
+        let mut chars = quoted.char_indices().peekable();
+        while let Some((index, c)) = chars.next() {
+            if c == '"' {
+                if let Some((_, '"')) = chars.peek() {
+                    chars.next();
+                    identifier.push('"');
+                } else {
+                    return Some((identifier, &quoted[index + 1..]));
+                }
+            } else {
+                identifier.push(c);
+            }
+        }
+        None
+    } else {
+        let end = input
+            .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '$'))
+            .unwrap_or(input.len());
+        if end == 0 {
+            None
+        } else {
+            Some((input[..end].to_owned(), &input[end..]))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parcel::*;
+
+    #[test]
+    fn it_parses_copy_statements() {
+        assert_eq!(
+            parse_copy_in(r#"COPY "public"."my data" (id, "fullName") FROM stdin;"#),
+            Some((
+                "public".to_owned(),
+                "my data".to_owned(),
+                vec!["id".to_owned(), "fullName".to_owned()]
+            ))
+        )
+    }
+
+    #[test]
+    fn it_ignores_other_statements() {
+        assert_eq!(
+            parse_copy_in("SET session_replication_role = replica;"),
+            None
+        )
+    }
+
+    #[test]
+    fn it_splits_rows() {
+        assert_eq!(
+            split_row("1\\tone\t\\N\ttwo\\\\three\\012"),
+            vec![
+                Some("1\tone".to_owned()),
+                None,
+                Some("two\\three\n".to_owned())
+            ]
+        )
+    }
+
+    #[test]
+    fn it_reads_blocks_and_manifest() {
+        let parcel = "\
+-- PARCEL ----------------------------------
+-- schema = \"public\"
+-- -----------------------------------------
+COPY public.users (id) FROM stdin;
+1
+2
+\\.
+COPY public.teams (id) FROM stdin;
+1
+";
+        let parcel = Parcel::read(parcel.as_bytes(), true).unwrap();
+        assert_eq!(parcel.manifest.unwrap().schema, "public");
+        assert_eq!(parcel.blocks.len(), 2);
+        assert_eq!(parcel.blocks[0].name, "users");
+        assert_eq!(parcel.blocks[0].rows, 2);
+        assert_eq!(parcel.blocks[0].lines, vec!["1", "2"]);
+        assert!(parcel.blocks[0].complete);
+        assert!(!parcel.blocks[1].complete);
+    }
+}
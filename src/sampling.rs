@@ -0,0 +1,282 @@
+use crate::inputfile::InputFile;
+use crate::sql_string::SqlString;
+use crate::table_config::TableSettings;
+use crate::{Options, Table};
+use postgres::Client;
+use std::error::Error;
+
+/// Settings for dumping a slice of each table rather than all of it.
+#[derive(Debug, Default)]
+pub struct Sampling {
+    /// Default row limit for every table.
+    limit: Option<u64>,
+    /// Default sample size for every table, as a percentage of its rows.
+    sample_percent: Option<f64>,
+    /// Seed for `TABLESAMPLE ... REPEATABLE`, so that samples are stable.
+    seed: f64,
+    /// Whether to add rows referenced by foreign keys from other tables'
+    /// samples.
+    pub references: bool,
+}
+
+/// The slice of one table to dump.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Sample {
+    pub limit: Option<u64>,
+    pub sample_percent: Option<f64>,
+}
+
+impl Sample {
+    pub fn is_all(&self) -> bool {
+        self.limit.is_none() && self.sample_percent.is_none()
+    }
+}
+
+impl Sampling {
+    pub fn load(file: &InputFile) -> Result<Sampling, Box<dyn Error>> {
+        if let Some(percent) = file.sample_percent {
+            check_percent("sample_percent", percent)?;
+        }
+        Ok(Sampling {
+            limit: file.limit,
+            sample_percent: file.sample_percent,
+            seed: file.sample_seed.unwrap_or(0.0),
+            references: file.sample_references.unwrap_or(false),
+        })
+    }
+
+    /// The slice to dump of a table with `settings`. `TABLESAMPLE` only works
+    /// on tables, so the default `sample_percent` isn't applied to tables
+    /// dumped through an override or a view.
+    pub fn sample(&self, settings: &TableSettings, from_table: bool) -> Sample {
+        Sample {
+            limit: settings.limit.or(self.limit),
+            sample_percent: settings
+                .sample_percent
+                .or(self.sample_percent.filter(|_| from_table)),
+        }
+    }
+
+    /// `TABLESAMPLE` clause for `sample`, if it has a `sample_percent`.
+    pub fn tablesample(&self, sample: &Sample) -> Option<String> {
+        sample.sample_percent.map(|percent| {
+            format!(
+                "TABLESAMPLE BERNOULLI ({percent}) REPEATABLE ({})",
+                self.seed
+            )
+        })
+    }
+}
+
+/// Check the percentage given by `setting`, which names the setting and the
+/// section it is in.
+pub fn check_percent(setting: &str, percent: f64) -> Result<(), Box<dyn Error>> {
+    if percent > 0.0 && percent <= 100.0 {
+        Ok(())
+    } else {
+        Err(format!("{setting} must be between 0 and 100, not {percent}").into())
+    }
+}
+
+/// A foreign key from `table` to `referenced_table`.
+#[derive(Debug)]
+pub struct ForeignKey {
+    pub table: String,
+    pub columns: Vec<String>,
+    pub referenced_table: String,
+    pub referenced_columns: Vec<String>,
+}
+
+/// Every foreign key between tables in `schema`, in constraint name order.
+pub fn foreign_keys(client: &mut Client, schema: &str) -> Result<Vec<ForeignKey>, Box<dyn Error>> {
+    let query = format!(
+        r#"
+        select
+          tbl.relname::text as table_name,
+          array(
+            select attname::text from unnest(conkey) with ordinality as k(attnum, n)
+            join pg_attribute on attrelid = conrelid and pg_attribute.attnum = k.attnum
+            order by n
+          ) as columns,
+          ref.relname::text as referenced_table,
+          array(
+            select attname::text from unnest(confkey) with ordinality as k(attnum, n)
+            join pg_attribute on attrelid = confrelid and pg_attribute.attnum = k.attnum
+            order by n
+          ) as referenced_columns
+        from pg_constraint
+        join pg_class tbl on tbl.oid = conrelid
+        join pg_class ref on ref.oid = confrelid
+        join pg_namespace on pg_namespace.oid = tbl.relnamespace
+        where contype = 'f'
+        and pg_namespace.nspname = {schema}
+        and ref.relnamespace = tbl.relnamespace
+        order by conname
+        "#,
+        schema = schema.to_owned().sql_value(),
+    );
+    Ok(client
+        .query(&query, &[])?
+        .into_iter()
+        .map(|row| ForeignKey {
+            table: row.get("table_name"),
+            columns: row.get("columns"),
+            referenced_table: row.get("referenced_table"),
+            referenced_columns: row.get("referenced_columns"),
+        })
+        .collect())
+}
+
+fn column_tuple(columns: &[String]) -> String {
+    let columns: Vec<String> = columns.iter().map(|c| c.sql_identifier()).collect();
+    format!("({})", columns.join(", "))
+}
+
+/// For every sampled table, find the rows that other tables' dumped rows
+/// refer to with foreign keys, so that they can be dumped along with the
+/// sample. Tables that are dumped in full already have every row they could
+/// be asked for.
+pub fn add_referenced_rows(
+    client: &mut Client,
+    options: &Options,
+    tables: &mut [Table],
+) -> Result<(), Box<dyn Error>> {
+    let foreign_keys = foreign_keys(client, &options.schema)?;
+
+    let mut visited = vec![false; tables.len()];
+    for index in 0..tables.len() {
+        resolve(index, options, &foreign_keys, tables, &mut visited)?;
+    }
+    Ok(())
+}
+
+/// Common table expression holding the rows dumped from `table`.
+fn cte_name(table: &str) -> String {
+    format!("pg_parcel_rows_{table}").sql_identifier()
+}
+
+/// Fill in `referenced_rows` and `with` for `tables[index]`, after doing so
+/// for every table referring to it. Foreign key cycles are cut where they are
+/// found.
+fn resolve(
+    index: usize,
+    options: &Options,
+    foreign_keys: &[ForeignKey],
+    tables: &mut [Table],
+    visited: &mut [bool],
+) -> Result<(), Box<dyn Error>> {
+    if visited[index] {
+        return Ok(());
+    }
+    visited[index] = true;
+    if tables[index].sample(options).is_all() {
+        return Ok(());
+    }
+
+    let name = tables[index].name.clone();
+    let mut referenced_rows = Vec::new();
+    // Each referencing table's query goes in once, however many paths lead
+    // to it, rather than inside every query that refers to it.
+    let mut with: Vec<(String, String)> = Vec::new();
+    for foreign_key in foreign_keys.iter().filter(|fk| fk.referenced_table == name) {
+        let Some(referencing) = tables.iter().position(|t| t.name == foreign_key.table) else {
+            continue;
+        };
+        resolve(referencing, options, foreign_keys, tables, visited)?;
+        let cte = cte_name(&foreign_key.table);
+        let ctes = tables[referencing]
+            .with
+            .iter()
+            .cloned()
+            .chain([(cte.clone(), tables[referencing].rows_query(options))]);
+        for (cte_name, query) in ctes {
+            if !with.iter().any(|(name, _)| *name == cte_name) {
+                with.push((cte_name, query));
+            }
+        }
+        referenced_rows.push(format!(
+            "{} IN (SELECT {} FROM {cte})",
+            column_tuple(&foreign_key.referenced_columns),
+            foreign_key
+                .columns
+                .iter()
+                .map(|c| c.sql_identifier())
+                .collect::<Vec<String>>()
+                .join(", "),
+        ));
+    }
+    if !referenced_rows.is_empty() && tables[index].primary_key.is_empty() {
+        return Err(format!(
+            "sample_references: {name} has no primary key to tell sampled rows from referenced ones. Give it one, or dump it in full."
+        )
+        .into());
+    }
+    tables[index].referenced_rows = referenced_rows;
+    tables[index].with = with;
+    Ok(())
+}
+
+/// Combine the `sampled` rows of a table with the rows `referenced_rows`
+/// picks out of `all` of them, matching rows up by `primary_key`, in the
+/// table's `order_by` order if it has one.
+pub fn with_referenced_rows(
+    sampled: String,
+    all: String,
+    primary_key: &[String],
+    referenced_rows: &[String],
+    order_by: Option<&String>,
+) -> String {
+    if referenced_rows.is_empty() {
+        return sampled;
+    }
+    let referenced = referenced_rows.join(" OR ");
+    let key: Vec<String> = primary_key.iter().map(|c| c.sql_identifier()).collect();
+    let query = format!(
+        "SELECT * FROM ({all}) AS referenced WHERE ({key}) IN (SELECT {key} FROM ({sampled}) AS sampled) OR {referenced}",
+        key = key.join(", ")
+    );
+    match order_by {
+        Some(order_by) => format!("{query} ORDER BY {order_by}"),
+        None => query,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sampling::*;
+
+    #[test]
+    fn it_prefers_table_settings_to_defaults() {
+        let sampling = Sampling {
+            limit: Some(100),
+            sample_percent: Some(10.0),
+            ..Sampling::default()
+        };
+        let settings = TableSettings {
+            sample_percent: Some(50.0),
+            ..TableSettings::default()
+        };
+        let users = sampling.sample(&settings, true);
+        assert_eq!(users.limit, Some(100));
+        assert_eq!(users.sample_percent, Some(50.0));
+        // Overrides and views can't be sampled by default.
+        let teams = sampling.sample(&TableSettings::default(), false);
+        assert_eq!(teams.sample_percent, None);
+    }
+
+    #[test]
+    fn it_keeps_the_order_of_sampled_and_referenced_rows() {
+        let query = with_referenced_rows(
+            "SELECT * FROM (SELECT id, name FROM users) AS limited ORDER BY name LIMIT 10"
+                .to_owned(),
+            "SELECT id, name FROM users".to_owned(),
+            &["id".to_owned()],
+            &["(id) IN (SELECT user_id FROM pg_parcel_rows_posts)".to_owned()],
+            Some(&"name".to_owned()),
+        );
+        assert_eq!(
+            query,
+            "SELECT * FROM (SELECT id, name FROM users) AS referenced WHERE (\"id\") IN (SELECT \"id\" FROM (SELECT * FROM (SELECT id, name FROM users) AS limited ORDER BY name LIMIT 10) AS sampled) OR (id) IN (SELECT user_id FROM pg_parcel_rows_posts) ORDER BY name"
+        );
+    }
+}
@@ -0,0 +1,151 @@
+use crate::matviews::MaterializedView;
+use crate::sql_string::SqlString;
+use crate::{Options, Table};
+use postgres::config::{Config, Host, SslMode};
+use std::error::Error;
+use std::io::{ErrorKind, Write};
+use std::process::Command;
+
+/// Which part of the schema to dump, in `pg_dump --section` terms.
+#[derive(Debug, Clone, Copy)]
+pub enum Section {
+    /// Tables, sequences, column defaults: everything needed to load data.
+    PreData,
+    /// Indexes, constraints and triggers, which are quicker to create after
+    /// the data is loaded.
+    PostData,
+}
+
+impl Section {
+    fn as_arg(&self) -> &'static str {
+        match self {
+            Section::PreData => "--section=pre-data",
+            Section::PostData => "--section=post-data",
+        }
+    }
+}
+
+/// Write DDL for `tables` and `materialized_views` with `pg_dump`, reading
+/// from the exported `snapshot` so that it matches the data.
+pub fn write_schema(
+    out: &mut impl Write,
+    options: &Options,
+    tables: &[Table],
+    materialized_views: &[MaterializedView],
+    snapshot: &str,
+    section: Section,
+) -> Result<(), Box<dyn Error>> {
+    let mut command = Command::new("pg_dump");
+    command
+        .arg("--schema-only")
+        .arg(section.as_arg())
+        .arg("--no-owner")
+        .arg("--no-privileges")
+        .arg(format!("--snapshot={snapshot}"));
+    set_connection_env(&mut command, options)?;
+    // Partitions need their parents to be created, and parents dumped as a
+    // whole need their partitions to hold the rows.
+    let mut names: Vec<&String> = tables
+        .iter()
+        .flat_map(|table| {
+            std::iter::once(&table.name)
+                .chain(table.ancestors.iter())
+                .chain(table.partitions.iter())
+        })
+        .chain(materialized_views.iter().map(|view| &view.name))
+        .collect();
+    names.sort();
+    names.dedup();
+    for name in names.into_iter() {
+        command.arg(format!(
+            "--table={}.{}",
+            options.schema.sql_identifier(),
+            name.sql_identifier()
+        ));
+    }
+    let output = command.output().map_err(|err| match err.kind() {
+        ErrorKind::NotFound => "--with-schema needs pg_dump, which was not found".into(),
+        _ => Box::<dyn Error>::from(err),
+    })?;
+    if !output.status.success() {
+        return Err(format!(
+            "pg_dump failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    out.write_all(&without_refreshes(&output.stdout))?;
+    Ok(())
+}
+
+/// Drop `REFRESH MATERIALIZED VIEW` statements from `pg_dump`'s output.
+/// `--schema-only` leaves them out already, but the parcel refreshes its
+/// views itself, after the data is loaded, and they shouldn't be refreshed
+/// twice.
+fn without_refreshes(dump: &[u8]) -> Vec<u8> {
+    dump.split_inclusive(|&byte| byte == b'\n')
+        .filter(|line| !line.starts_with(b"REFRESH MATERIALIZED VIEW "))
+        .flatten()
+        .copied()
+        .collect()
+}
+
+/// Pass the connection to `pg_dump` in libpq's environment variables, so that
+/// the password doesn't show up in its command line.
+fn set_connection_env(command: &mut Command, options: &Options) -> Result<(), Box<dyn Error>> {
+    let config: Config = options.database_url.parse()?;
+    let hosts: Vec<String> = config
+        .get_hosts()
+        .iter()
+        .map(|host| match host {
+            Host::Tcp(host) => host.clone(),
+            #[cfg(unix)]
+            Host::Unix(path) => path.display().to_string(),
+        })
+        .collect();
+    if !hosts.is_empty() {
+        command.env("PGHOST", hosts.join(","));
+    }
+    let ports: Vec<String> = config.get_ports().iter().map(u16::to_string).collect();
+    if !ports.is_empty() {
+        command.env("PGPORT", ports.join(","));
+    }
+    if let Some(user) = config.get_user() {
+        command.env("PGUSER", user);
+    }
+    if let Some(password) = config.get_password() {
+        command.env("PGPASSWORD", String::from_utf8_lossy(password).into_owned());
+    }
+    if let Some(dbname) = config.get_dbname() {
+        command.env("PGDATABASE", dbname);
+    }
+    if let Some(application_name) = config.get_application_name() {
+        command.env("PGAPPNAME", application_name);
+    }
+    if let Some(pg_options) = config.get_options() {
+        command.env("PGOPTIONS", pg_options);
+    }
+    // `require` encrypts without checking the server's certificate.
+    let ssl_mode = match config.get_ssl_mode() {
+        _ if options.accept_invalid_certs => "require",
+        SslMode::Disable => "disable",
+        SslMode::Require => "require",
+        _ => "prefer",
+    };
+    command.env("PGSSLMODE", ssl_mode);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::schema::*;
+
+    #[test]
+    fn it_leaves_refreshes_to_the_parcel() {
+        let dump = b"CREATE INDEX users_email ON public.users (email);\nREFRESH MATERIALIZED VIEW public.customer_counts;\n";
+        assert_eq!(
+            without_refreshes(dump),
+            b"CREATE INDEX users_email ON public.users (email);\n"
+        );
+    }
+}
@@ -0,0 +1,190 @@
+use crate::sql_string::SqlString;
+use crate::{Options, Table};
+use lazy_static::lazy_static;
+use postgres::Client;
+use regex::{Captures, Regex};
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+
+lazy_static! {
+    static ref PLACEHOLDER: Regex = Regex::new(r":selected\(\s*(\w+)\s*,\s*(\w+)\s*\)").unwrap();
+}
+
+/// Temporary table holding the keys selected from `table`.
+fn temp_table(table: &str) -> String {
+    format!("pg_parcel_selected_{table}").sql_identifier()
+}
+
+/// Replace each `:selected(table, column)` placeholder in `query` with a
+/// subquery on the keys selected from that table.
+pub fn substitute(query: &str) -> String {
+    PLACEHOLDER
+        .replace_all(query, |captures: &Captures| {
+            format!(
+                "(SELECT {} FROM {})",
+                captures[2].to_owned().sql_identifier(),
+                temp_table(&captures[1])
+            )
+        })
+        .into_owned()
+}
+
+/// The `(table, column)` pairs in `table`'s `:selected` placeholders.
+fn references(table: &Table) -> Vec<(String, String)> {
+    let texts = table
+        .settings
+        .query
+        .iter()
+        .chain(&table.settings.where_clause)
+        .chain(&table.filters);
+    texts
+        .flat_map(|text| PLACEHOLDER.captures_iter(text))
+        .map(|captures| (captures[1].to_owned(), captures[2].to_owned()))
+        .collect()
+}
+
+/// Whether `table` holds rows selected from the table called `name`, either
+/// as that table or as one of its partitions.
+fn holds(table: &Table, name: &str) -> bool {
+    table.name == name || table.ancestors.iter().any(|ancestor| ancestor == name)
+}
+
+/// The columns referenced by any `:selected` placeholder, by table name.
+fn referenced_columns(tables: &[Table]) -> BTreeMap<String, BTreeSet<String>> {
+    let mut columns: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for (name, column) in tables.iter().flat_map(references) {
+        columns.entry(name).or_default().insert(column);
+    }
+    columns
+}
+
+/// Check every `:selected` placeholder, and sort `tables` so that each table
+/// comes after the tables its placeholders refer to, otherwise keeping their
+/// order.
+pub fn dump_order(mut tables: Vec<Table>) -> Result<Vec<Table>, Box<dyn Error>> {
+    let dependencies: Vec<Vec<String>> = tables
+        .iter()
+        .map(|table| {
+            references(table)
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect()
+        })
+        .collect();
+    for (table, table_dependencies) in tables.iter().zip(&dependencies) {
+        for (name, column) in references(table) {
+            let sources: Vec<&Table> = tables.iter().filter(|t| holds(t, &name)).collect();
+            if sources.is_empty() {
+                return Err(format!(
+                    "{} uses :selected({name}, {column}), but {name} isn't being dumped",
+                    table.name
+                )
+                .into());
+            }
+            if let Some(source) = sources
+                .iter()
+                .find(|source| !source.columns.iter().any(|c| c.name == column))
+            {
+                return Err(format!(
+                    "{} uses :selected({name}, {column}), but {} has no column {column} in the dump",
+                    table.name, source.name
+                )
+                .into());
+            }
+        }
+        if table_dependencies.iter().any(|name| holds(table, name)) {
+            return Err(format!("{} uses :selected on itself", table.name).into());
+        }
+    }
+
+    let mut pending: Vec<(Table, Vec<String>)> = tables.drain(..).zip(dependencies).collect();
+    let mut ordered: Vec<Table> = Vec::with_capacity(pending.len());
+    while !pending.is_empty() {
+        let ready = pending.iter().position(|(_, dependencies)| {
+            dependencies.iter().all(|name| {
+                !pending
+                    .iter()
+                    .any(|(pending_table, _)| holds(pending_table, name))
+            })
+        });
+        let Some(ready) = ready else {
+            let names: Vec<&str> = pending
+                .iter()
+                .map(|(table, _)| table.name.as_str())
+                .collect();
+            return Err(format!(
+                ":selected placeholders form a cycle between {}",
+                names.join(", ")
+            )
+            .into());
+        };
+        ordered.push(pending.remove(ready).0);
+    }
+    Ok(ordered)
+}
+
+/// Create an empty temporary table for each table referenced by a `:selected`
+/// placeholder. This must happen before the read-only transaction starts,
+/// which can write to temporary tables but not create them.
+pub fn create_temp_tables(
+    client: &mut Client,
+    options: &Options,
+    tables: &[Table],
+) -> Result<(), Box<dyn Error>> {
+    for (name, columns) in referenced_columns(tables) {
+        let columns: Vec<String> = columns.iter().map(|c| c.sql_identifier()).collect();
+        client.execute(
+            &format!(
+                "CREATE TEMP TABLE {} AS SELECT {} FROM {}.{} WITH NO DATA",
+                temp_table(&name),
+                columns.join(", "),
+                options.schema.sql_identifier(),
+                name.sql_identifier()
+            ),
+            &[],
+        )?;
+    }
+    Ok(())
+}
+
+/// Fill the temporary tables from `create_temp_tables` with the keys of the
+/// rows selected from each table, in dump order, so that every placeholder's
+/// table is filled before any query using it runs.
+pub fn fill_temp_tables(
+    client: &mut Client,
+    options: &Options,
+    tables: &[Table],
+) -> Result<(), Box<dyn Error>> {
+    let referenced = referenced_columns(tables);
+    for table in tables.iter() {
+        for (name, columns) in referenced.iter() {
+            if !holds(table, name) {
+                continue;
+            }
+            let columns: Vec<String> = columns.iter().map(|c| c.sql_identifier()).collect();
+            let columns = columns.join(", ");
+            client.execute(
+                &format!(
+                    "INSERT INTO {} ({columns}) SELECT {columns} FROM ({}) AS selected",
+                    temp_table(name),
+                    table.copy_out_query(options)
+                ),
+                &[],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::selected::*;
+
+    #[test]
+    fn it_substitutes_placeholders() {
+        assert_eq!(
+            substitute("select * from posts where author_id in :selected( users , id )"),
+            "select * from posts where author_id in (SELECT \"id\" FROM \"pg_parcel_selected_users\")"
+        );
+    }
+}
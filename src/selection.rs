@@ -0,0 +1,312 @@
+use crate::columns;
+use crate::overrides_dir;
+use crate::table_config::TableSettings;
+use crate::Options;
+use postgres::Client;
+use regex::Regex;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+
+/// Which tables to dump, from `include_tables` and table settings in the
+/// config file and `--table` and `--exclude-table` on the command line.
+///
+/// Rules are tried in this order, and the first one that matches decides:
+///
+/// 1. `--exclude-table` excludes.
+/// 2. `--table` includes. If it is given at all, tables it doesn't match
+///    are excluded, and the config file's rules are ignored.
+/// 3. `skip`, or `skip_tables`, excludes.
+/// 4. `features` excludes, if none of the table's features are enabled, and
+///    `unless_features` excludes, if any of its features are enabled.
+/// 5. `include_tables` includes. If it is given at all, tables it doesn't
+///    match are excluded.
+///
+/// Anything else is included. A rule matches a partition if it matches any
+/// of the partitioned tables above it.
+#[derive(Debug, Default)]
+pub struct TableSelection {
+    exclude_table: Vec<Pattern>,
+    table: Vec<Pattern>,
+    include_tables: Option<Vec<Pattern>>,
+}
+
+/// A pattern as written, and compiled.
+#[derive(Debug)]
+struct Pattern(String, Regex);
+
+/// Whether a table is dumped, and why.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Verdict {
+    pub included: bool,
+    pub rule: Rule,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Rule {
+    ExcludeTable(String),
+    Table(String),
+    NotTable,
+    /// Skipped by the section or `skip_tables` pattern described.
+    Skip(String),
+    Features(Vec<String>),
+    UnlessFeatures(String),
+    IncludeTables(String),
+    NotIncludeTables,
+    Default,
+    /// A partitioned table whose partitions are dumped one by one.
+    Partitions,
+    /// A partition dumped through the partitioned table named.
+    ThroughRoot(String),
+    /// A partition whose bounds rule out every requested ID.
+    PartitionBounds,
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rule::ExcludeTable(pattern) => write!(f, "--exclude-table {pattern}"),
+            Rule::Table(pattern) => write!(f, "--table {pattern}"),
+            Rule::NotTable => write!(f, "not matched by --table"),
+            Rule::Skip(origin) => write!(f, "skip in {origin}"),
+            Rule::Features(features) => {
+                write!(f, "none of features {} enabled", features.join(", "))
+            }
+            Rule::UnlessFeatures(feature) => write!(f, "unless_features {feature} enabled"),
+            Rule::IncludeTables(pattern) => write!(f, "include_tables {pattern:?}"),
+            Rule::NotIncludeTables => write!(f, "not matched by include_tables"),
+            Rule::Default => write!(f, "default"),
+            Rule::Partitions => write!(f, "dumped partition by partition"),
+            Rule::ThroughRoot(root) => write!(f, "dumped through {root}"),
+            Rule::PartitionBounds => write!(f, "partition bounds rule out every ID"),
+        }
+    }
+}
+
+fn compile(patterns: impl IntoIterator<Item = String>) -> Result<Vec<Pattern>, Box<dyn Error>> {
+    let mut patterns: Vec<String> = patterns.into_iter().collect();
+    patterns.sort();
+    Ok(patterns
+        .into_iter()
+        .map(|pattern| Ok(Pattern(pattern.clone(), Regex::new(&pattern)?)))
+        .collect::<Result<_, regex::Error>>()?)
+}
+
+/// Command line patterns name whole tables, as in `pg_dump`.
+fn compile_anchored(patterns: Vec<String>) -> Result<Vec<Pattern>, Box<dyn Error>> {
+    Ok(patterns
+        .into_iter()
+        .map(|pattern| {
+            let regex = Regex::new(&format!("^(?:{pattern})$"))?;
+            Ok(Pattern(pattern, regex))
+        })
+        .collect::<Result<_, regex::Error>>()?)
+}
+
+impl TableSelection {
+    pub fn new(
+        include_tables: Option<HashSet<String>>,
+        table: Vec<String>,
+        exclude_table: Vec<String>,
+    ) -> Result<TableSelection, Box<dyn Error>> {
+        Ok(TableSelection {
+            exclude_table: compile_anchored(exclude_table)?,
+            table: compile_anchored(table)?,
+            include_tables: include_tables.map(compile).transpose()?,
+        })
+    }
+
+    /// Decide whether to dump the table called `name`, a partition of
+    /// `ancestors` if it has any, given its `settings` and the enabled
+    /// `features`.
+    pub fn verdict(
+        &self,
+        name: &str,
+        ancestors: &[String],
+        settings: &TableSettings,
+        features: &HashSet<String>,
+    ) -> Verdict {
+        let names: Vec<&str> = std::iter::once(name)
+            .chain(ancestors.iter().map(String::as_str))
+            .collect();
+        let matching = |patterns: &[Pattern]| {
+            patterns
+                .iter()
+                .find(|Pattern(_, regex)| names.iter().any(|name| regex.is_match(name)))
+                .map(|Pattern(pattern, _)| pattern.clone())
+        };
+        let (included, rule) = if let Some(pattern) = matching(&self.exclude_table) {
+            (false, Rule::ExcludeTable(pattern))
+        } else if let Some(pattern) = matching(&self.table) {
+            (true, Rule::Table(pattern))
+        } else if !self.table.is_empty() {
+            (false, Rule::NotTable)
+        } else if let (Some(true), Some(origin)) = (settings.skip, &settings.skip_origin) {
+            (false, Rule::Skip(origin.clone()))
+        } else if let Some(table_features) = settings
+            .features
+            .as_ref()
+            .filter(|table_features| !table_features.iter().any(|f| features.contains(f)))
+        {
+            (false, Rule::Features(table_features.clone()))
+        } else if let Some(feature) = settings
+            .unless_features
+            .iter()
+            .flatten()
+            .find(|feature| features.contains(*feature))
+        {
+            (false, Rule::UnlessFeatures(feature.clone()))
+        } else if let Some(include_tables) = &self.include_tables {
+            match matching(include_tables) {
+                Some(pattern) => (true, Rule::IncludeTables(pattern)),
+                None => (false, Rule::NotIncludeTables),
+            }
+        } else {
+            (true, Rule::Default)
+        };
+        Verdict { included, rule }
+    }
+}
+
+/// Print every table in the schema, whether it would be dumped, the rule that
+/// decided it, and any conditions its rows are filtered by.
+pub fn list_tables(client: &mut Client, options: &Options) -> Result<(), Box<dyn Error>> {
+    overrides_dir::report_orphans(client, options)?;
+    let query = crate::tables_query(client, options)?;
+    let rows = client.query(&query, &[])?;
+    let tables: Vec<(String, Vec<String>, Vec<String>, Verdict)> = rows
+        .iter()
+        .map(|row| {
+            (
+                row.get("table_name"),
+                row.get("ancestors"),
+                row.get("column_names"),
+                crate::table_verdict(client, options, row),
+            )
+        })
+        .collect();
+    let width = tables
+        .iter()
+        .map(|(name, ..)| name.len())
+        .max()
+        .unwrap_or(0)
+        .max("Table name".len());
+    println!("  Status | {:width$} | Rule", "Table name");
+    for (name, ancestors, column_names, verdict) in tables.iter() {
+        let settings = options.tables.settings(name, ancestors);
+        let status = if verdict.included {
+            "included"
+        } else {
+            "excluded"
+        };
+        println!("{status:>8} | {name:width$} | {}", verdict.rule);
+        if !verdict.included {
+            continue;
+        }
+        if options.strict
+            && !column_names.contains(&options.column_name)
+            && settings.query.is_none()
+            && !options.is_global(name, ancestors)
+        {
+            println!(
+                "{:>8} | {:width$} |   unscoped, so strict mode refuses to dump it",
+                "", ""
+            );
+        }
+        // Conditions on the table's rows, other than `column_name`.
+        let conditions = settings
+            .where_clause
+            .into_iter()
+            .chain(columns::filter_conditions(
+                &options.column_filters,
+                column_names,
+            ));
+        for condition in conditions {
+            println!("{:>8} | {:width$} |   where {condition}", "", "");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::selection::*;
+
+    fn patterns(patterns: &[&str]) -> Vec<String> {
+        patterns.iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn it_applies_rules_in_order() {
+        let selection = TableSelection::new(
+            Some(patterns(&["^user", "^obsolete_"]).into_iter().collect()),
+            Vec::new(),
+            patterns(&["user_files"]),
+        )
+        .unwrap();
+        let skip = TableSettings {
+            skip: Some(true),
+            skip_origin: Some("skip_tables \"^obsolete_\"".to_owned()),
+            ..TableSettings::default()
+        };
+        let features = HashSet::new();
+        let rule = |name: &str, settings: &TableSettings| {
+            selection.verdict(name, &[], settings, &features).rule
+        };
+        let none = TableSettings::default();
+        assert_eq!(
+            rule("user_files", &none),
+            Rule::ExcludeTable("user_files".into())
+        );
+        assert_eq!(rule("users", &none), Rule::IncludeTables("^user".into()));
+        assert_eq!(
+            rule("obsolete_users", &skip),
+            Rule::Skip("skip_tables \"^obsolete_\"".into())
+        );
+        assert_eq!(rule("teams", &none), Rule::NotIncludeTables);
+    }
+
+    #[test]
+    fn it_lets_table_flags_override_the_config_file() {
+        let selection = TableSelection::new(None, patterns(&["events"]), Vec::new()).unwrap();
+        let skip = TableSettings {
+            skip: Some(true),
+            skip_origin: Some("[tables.\"events*\"]".to_owned()),
+            ..TableSettings::default()
+        };
+        let features = HashSet::new();
+        let verdict = selection.verdict("events_acme", &["events".to_owned()], &skip, &features);
+        assert!(verdict.included);
+        assert_eq!(verdict.rule, Rule::Table("events".into()));
+        let verdict = selection.verdict("users", &[], &TableSettings::default(), &features);
+        assert!(!verdict.included);
+    }
+
+    #[test]
+    fn it_skips_tables_without_enabled_features() {
+        let selection = TableSelection::new(None, Vec::new(), Vec::new()).unwrap();
+        let settings = TableSettings {
+            features: Some(vec!["audit".to_owned()]),
+            ..TableSettings::default()
+        };
+        let verdict = selection.verdict("audit_log", &[], &settings, &HashSet::new());
+        assert_eq!(verdict.rule, Rule::Features(vec!["audit".to_owned()]));
+        let features = HashSet::from(["audit".to_owned()]);
+        assert!(
+            selection
+                .verdict("audit_log", &[], &settings, &features)
+                .included
+        );
+        let settings = TableSettings {
+            unless_features: Some(vec!["audit".to_owned()]),
+            ..TableSettings::default()
+        };
+        let verdict = selection.verdict("audit_summary", &[], &settings, &features);
+        assert_eq!(verdict.rule, Rule::UnlessFeatures("audit".to_owned()));
+        assert!(
+            selection
+                .verdict("audit_summary", &[], &settings, &HashSet::new())
+                .included
+        );
+    }
+}
@@ -0,0 +1,149 @@
+use crate::sql_string::SqlString;
+use crate::{Options, Table};
+use clap::ValueEnum;
+use postgres::Client;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io::Write;
+
+/// Where `--reset-sequences` takes each sequence's new value from.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum SequenceReset {
+    /// The largest value loaded into any column using the sequence.
+    Max,
+    /// The sequence's current value in the source database.
+    Source,
+}
+
+/// A sequence used by one or more dumped columns.
+#[derive(Debug)]
+pub struct Sequence {
+    pub schema: String,
+    pub name: String,
+    /// `(table, column)` pairs using the sequence.
+    pub columns: Vec<(String, String)>,
+}
+
+impl Sequence {
+    fn sql_identifier(&self) -> String {
+        format!(
+            "{}.{}",
+            self.schema.sql_identifier(),
+            self.name.sql_identifier()
+        )
+    }
+}
+
+/// Find the sequences owned by, or used in the defaults of, columns in
+/// `tables`.
+pub fn find_sequences(
+    client: &mut Client,
+    options: &Options,
+    tables: &[Table],
+) -> Result<Vec<Sequence>, Box<dyn Error>> {
+    let query = format!(
+        r#"
+        -- Sequences owned by a column, including identity columns.
+        select seq_ns.nspname::text as sequence_schema, seq.relname::text as sequence_name,
+               tbl.relname::text as table_name, att.attname::text as column_name
+        from pg_depend dep
+        join pg_class seq on seq.oid = dep.objid and seq.relkind = 'S'
+        join pg_namespace seq_ns on seq_ns.oid = seq.relnamespace
+        join pg_class tbl on tbl.oid = dep.refobjid
+        join pg_namespace tbl_ns on tbl_ns.oid = tbl.relnamespace
+        join pg_attribute att on att.attrelid = dep.refobjid and att.attnum = dep.refobjsubid
+        where dep.classid = 'pg_class'::regclass
+        and dep.refclassid = 'pg_class'::regclass
+        and dep.deptype in ('a', 'i')
+        and tbl_ns.nspname = {schema}
+        union
+        -- Sequences used by a column default, such as `nextval('my_seq')`.
+        select seq_ns.nspname::text, seq.relname::text, tbl.relname::text, att.attname::text
+        from pg_attrdef def
+        join pg_depend dep on dep.classid = 'pg_attrdef'::regclass and dep.objid = def.oid
+        join pg_class seq on seq.oid = dep.refobjid and dep.refclassid = 'pg_class'::regclass and seq.relkind = 'S'
+        join pg_namespace seq_ns on seq_ns.oid = seq.relnamespace
+        join pg_class tbl on tbl.oid = def.adrelid
+        join pg_namespace tbl_ns on tbl_ns.oid = tbl.relnamespace
+        join pg_attribute att on att.attrelid = def.adrelid and att.attnum = def.adnum
+        where tbl_ns.nspname = {schema}
+        order by 1, 2, 3, 4
+        "#,
+        schema = options.schema.sql_value(),
+    );
+
+    let mut sequences: BTreeMap<(String, String), Sequence> = BTreeMap::new();
+    for row in client.query(&query, &[])? {
+        let table_name: String = row.get("table_name");
+        let column_name: String = row.get("column_name");
+        let dumped = tables.iter().any(|table| {
+            table.name == table_name && table.columns.iter().any(|c| c.name == column_name)
+        });
+        if !dumped {
+            continue;
+        }
+        let schema: String = row.get("sequence_schema");
+        let name: String = row.get("sequence_name");
+        sequences
+            .entry((schema.clone(), name.clone()))
+            .or_insert_with(|| Sequence {
+                schema,
+                name,
+                columns: Vec::new(),
+            })
+            .columns
+            .push((table_name, column_name));
+    }
+    Ok(sequences.into_values().collect())
+}
+
+/// Write a `setval` statement for each sequence.
+pub fn write_setvals(
+    out: &mut impl Write,
+    client: &mut Client,
+    options: &Options,
+    sequences: &[Sequence],
+    reset: SequenceReset,
+) -> Result<(), Box<dyn Error>> {
+    for sequence in sequences.iter() {
+        let identifier = sequence.sql_identifier();
+        match reset {
+            SequenceReset::Max => {
+                // `greatest` ignores NULLs, and an empty table leaves the
+                // sequence alone.
+                let maximums = sequence
+                    .columns
+                    .iter()
+                    .map(|(table, column)| {
+                        format!(
+                            "(SELECT max({}) FROM {}.{})",
+                            column.sql_identifier(),
+                            options.schema.sql_identifier(),
+                            table.sql_identifier()
+                        )
+                    })
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                writeln!(
+                    out,
+                    "SELECT pg_catalog.setval({}, max_value) FROM (SELECT greatest({maximums}) AS max_value) AS loaded WHERE max_value IS NOT NULL;",
+                    identifier.sql_value()
+                )?;
+            }
+            SequenceReset::Source => {
+                let row = client.query_one(
+                    &format!("SELECT last_value::text, is_called FROM {identifier}"),
+                    &[],
+                )?;
+                let last_value: String = row.get(0);
+                let is_called: bool = row.get(1);
+                writeln!(
+                    out,
+                    "SELECT pg_catalog.setval({}, {last_value}, {is_called});",
+                    identifier.sql_value()
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,98 @@
+use crate::suggestion::did_you_mean;
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use std::collections::HashMap;
+use std::error::Error;
+
+/// Named SQL fragments from the `[snippets]` section of the config file,
+/// referenced as `{{name}}` from overrides, `where` conditions and other
+/// snippets.
+#[derive(Debug, Default)]
+pub struct Snippets {
+    snippets: HashMap<String, String>,
+}
+
+lazy_static! {
+    static ref REFERENCE: Regex = Regex::new(r"\{\{\s*(\w+)\s*\}\}").unwrap();
+}
+
+impl Snippets {
+    pub fn new(snippets: HashMap<String, String>) -> Snippets {
+        Snippets { snippets }
+    }
+
+    /// Replace every `{{name}}` in `sql` with its snippet, expanded in turn.
+    pub fn expand(&self, sql: &str) -> Result<String, Box<dyn Error>> {
+        self.expand_within(sql, &mut Vec::new())
+    }
+
+    /// `expand`, inside the snippets named in `stack`.
+    fn expand_within(&self, sql: &str, stack: &mut Vec<String>) -> Result<String, Box<dyn Error>> {
+        let mut error = None;
+        let expanded = REFERENCE.replace_all(sql, |captures: &Captures| {
+            match self.snippet(&captures[1], stack) {
+                Ok(snippet) => snippet,
+                Err(e) => {
+                    error.get_or_insert(e);
+                    String::new()
+                }
+            }
+        });
+        match error {
+            Some(error) => Err(error),
+            None => Ok(expanded.into_owned()),
+        }
+    }
+
+    fn snippet(&self, name: &str, stack: &mut Vec<String>) -> Result<String, Box<dyn Error>> {
+        let Some(snippet) = self.snippets.get(name) else {
+            let names: Vec<String> = self.snippets.keys().cloned().collect();
+            let suggestion = did_you_mean(&names, name);
+            return Err(format!("No snippet named `{name}` in [snippets].{suggestion}").into());
+        };
+        if stack.iter().any(|outer| outer == name) {
+            stack.push(name.to_owned());
+            return Err(
+                format!("Snippet `{name}` refers to itself: {}", stack.join(" -> ")).into(),
+            );
+        }
+        stack.push(name.to_owned());
+        let expanded = self.expand_within(snippet, stack)?;
+        stack.pop();
+        Ok(expanded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::snippets::*;
+
+    fn snippets(snippets: &[(&str, &str)]) -> Snippets {
+        Snippets::new(
+            snippets
+                .iter()
+                .map(|(name, sql)| (name.to_string(), sql.to_string()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn it_expands_nested_snippets() {
+        let snippets = snippets(&[
+            ("tenant_users", "select id from users where {{ in_tenant }}"),
+            ("in_tenant", "customer_id in :ids"),
+        ]);
+        assert_eq!(
+            snippets.expand("user_id in ({{tenant_users}})").unwrap(),
+            "user_id in (select id from users where customer_id in :ids)"
+        );
+    }
+
+    #[test]
+    fn it_rejects_unknown_and_recursive_snippets() {
+        let snippets = snippets(&[("a", "{{b}}"), ("b", "{{a}}")]);
+        let error = snippets.expand("{{a}}").unwrap_err().to_string();
+        assert_eq!(error, "Snippet `a` refers to itself: a -> b -> a");
+        assert!(snippets.expand("{{c}}").is_err());
+    }
+}
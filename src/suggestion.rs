@@ -0,0 +1,22 @@
+use suggest::Suggest;
+
+/// ` Did you mean `<closest>`?`, naming the closest of `names` to `name`, to
+/// end a message about `name` not being found. Empty if none is close.
+pub fn did_you_mean(names: &impl Suggest, name: &str) -> String {
+    match names.suggest(name) {
+        Some(suggestion) => format!(" Did you mean `{suggestion}`?"),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::suggestion::*;
+
+    #[test]
+    fn it_suggests_close_names_only() {
+        let names = vec!["acme".to_owned(), "globex".to_owned()];
+        assert_eq!(did_you_mean(&names, "acne"), " Did you mean `acme`?");
+        assert_eq!(did_you_mean(&names, "initech"), "");
+    }
+}
@@ -0,0 +1,299 @@
+use crate::inputfile::{InputFile, TableConfig};
+use crate::overrides_dir::OverrideFile;
+use crate::sampling;
+use crate::snippets::Snippets;
+use crate::suggestion::did_you_mean;
+use regex::Regex;
+use std::cmp::Reverse;
+use std::error::Error;
+
+/// The `[tables."<key>"]` sections of the config file, along with the older
+/// `overrides` and `skip_tables` keys and the files in `overrides_dir`, which
+/// are shorthand for sections.
+///
+/// A key is a table name, a glob if it contains `*` or `?`, or a regular
+/// expression if it is wrapped in slashes, as in `"/^audit_/"`. Every section
+/// matching a table contributes to its settings, one setting at a time: each
+/// setting comes from the most specific section that has it. Table names are
+/// more specific than globs, and globs than regular expressions; among keys
+/// of the same kind, longer keys are more specific. Sections matching a
+/// partitioned table apply to its partitions, less specifically than those
+/// matching the partition itself, except for `query`.
+#[derive(Debug, Default)]
+pub struct TableConfigs {
+    /// Sorted from most to least specific.
+    sections: Vec<Section>,
+}
+
+#[derive(Debug)]
+struct Section {
+    key: String,
+    matcher: Matcher,
+    config: TableConfig,
+    /// Where the section came from, for `list-tables`.
+    origin: String,
+}
+
+#[derive(Debug)]
+enum Matcher {
+    Name(String),
+    Glob(Regex),
+    Regex(Regex),
+}
+
+impl Matcher {
+    fn new(key: &str) -> Result<Matcher, regex::Error> {
+        if let Some(pattern) = key.strip_prefix('/').and_then(|key| key.strip_suffix('/')) {
+            Ok(Matcher::Regex(Regex::new(pattern)?))
+        } else if key.contains(['*', '?']) {
+            Ok(Matcher::Glob(glob(key)?))
+        } else {
+            Ok(Matcher::Name(key.to_owned()))
+        }
+    }
+
+    fn is_match(&self, name: &str) -> bool {
+        match self {
+            Matcher::Name(key) => key == name,
+            Matcher::Glob(regex) | Matcher::Regex(regex) => regex.is_match(name),
+        }
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            Matcher::Name(_) => 0,
+            Matcher::Glob(_) => 1,
+            Matcher::Regex(_) => 2,
+        }
+    }
+}
+
+/// Compile a glob, where `*` matches any run of characters and `?` any one
+/// character, into a regular expression matching whole names.
+pub fn glob(pattern: &str) -> Result<Regex, regex::Error> {
+    Regex::new(&format!(
+        "^{}$",
+        regex::escape(pattern)
+            .replace(r"\*", ".*")
+            .replace(r"\?", ".")
+    ))
+}
+
+/// Settings for one table, merged from every section matching it.
+#[derive(Debug, Clone, Default)]
+pub struct TableSettings {
+    pub skip: Option<bool>,
+    /// Where `skip` came from, for `list-tables`.
+    pub skip_origin: Option<String>,
+    pub query: Option<String>,
+    pub where_clause: Option<String>,
+    pub limit: Option<u64>,
+    pub sample_percent: Option<f64>,
+    pub columns: Option<Vec<String>>,
+    pub features: Option<Vec<String>>,
+    pub unless_features: Option<Vec<String>>,
+    pub order_by: Option<String>,
+}
+
+impl TableSettings {
+    /// Fill in any settings not already set from `config`.
+    fn merge(&mut self, config: &TableConfig, origin: &str, with_query: bool) {
+        if self.skip.is_none() && config.skip.is_some() {
+            self.skip = config.skip;
+            self.skip_origin = Some(origin.to_owned());
+        }
+        if with_query {
+            self.query = self.query.take().or_else(|| config.query.clone());
+        }
+        self.where_clause = self
+            .where_clause
+            .take()
+            .or_else(|| config.where_clause.clone());
+        self.limit = self.limit.or(config.limit);
+        self.sample_percent = self.sample_percent.or(config.sample_percent);
+        self.columns = self.columns.take().or_else(|| config.columns.clone());
+        self.features = self.features.take().or_else(|| config.features.clone());
+        self.unless_features = self
+            .unless_features
+            .take()
+            .or_else(|| config.unless_features.clone());
+        self.order_by = self.order_by.take().or_else(|| config.order_by.clone());
+    }
+}
+
+impl TableConfigs {
+    /// Load sections from `file` and `override_files`, with `snippets`
+    /// expanded in their queries and conditions.
+    pub fn load(
+        file: &InputFile,
+        override_files: &[OverrideFile],
+        snippets: &Snippets,
+    ) -> Result<TableConfigs, Box<dyn Error>> {
+        let mut sections = Vec::new();
+        for (key, config) in file.tables.iter().flatten() {
+            if let Some(percent) = config.sample_percent {
+                sampling::check_percent(&format!("[tables.{key:?}] sample_percent"), percent)?;
+            }
+            let defined = file.features.clone().unwrap_or_default();
+            let features = config.features.iter().chain(&config.unless_features);
+            for feature in features.flatten() {
+                if !defined.contains(feature) {
+                    let suggestion = did_you_mean(&defined, feature);
+                    return Err(format!(
+                        "[tables.{key:?}] names feature `{feature}`, which isn't defined in features.{suggestion}"
+                    )
+                    .into());
+                }
+            }
+            let mut config = config.clone();
+            config.query = config.query.map(|q| snippets.expand(&q)).transpose()?;
+            config.where_clause = config
+                .where_clause
+                .map(|condition| snippets.expand(&condition))
+                .transpose()?;
+            sections.push(Section {
+                key: key.clone(),
+                matcher: Matcher::new(key)?,
+                config,
+                origin: format!("[tables.{key:?}]"),
+            });
+        }
+        let mut overrides: Vec<(&String, &String, String)> = file
+            .overrides
+            .iter()
+            .flatten()
+            .map(|(name, query)| (name, query, "[overrides]".to_owned()))
+            .collect();
+        overrides.sort();
+        for override_file in override_files.iter() {
+            overrides.push((
+                &override_file.table,
+                &override_file.query,
+                override_file.path.display().to_string(),
+            ));
+        }
+        for (name, query, origin) in overrides {
+            let conflict = sections
+                .iter()
+                .find(|section| &section.key == name && section.config.query.is_some());
+            if let Some(conflict) = conflict {
+                return Err(format!(
+                    "{name} has a query in both {} and {origin}",
+                    conflict.origin
+                )
+                .into());
+            }
+            sections.push(Section {
+                key: name.clone(),
+                matcher: Matcher::Name(name.clone()),
+                config: TableConfig {
+                    query: Some(snippets.expand(query)?),
+                    ..TableConfig::default()
+                },
+                origin,
+            });
+        }
+        for pattern in file.skip_tables.iter().flatten() {
+            sections.push(Section {
+                key: format!("/{pattern}/"),
+                matcher: Matcher::Regex(Regex::new(pattern)?),
+                config: TableConfig {
+                    skip: Some(true),
+                    ..TableConfig::default()
+                },
+                origin: format!("skip_tables {pattern:?}"),
+            });
+        }
+        sections.sort_by(|a, b| {
+            (a.matcher.rank(), Reverse(a.key.len()), &a.key).cmp(&(
+                b.matcher.rank(),
+                Reverse(b.key.len()),
+                &b.key,
+            ))
+        });
+        Ok(TableConfigs { sections })
+    }
+
+    /// Settings for the table called `name`, a partition of `ancestors` if it
+    /// has any, nearest first.
+    pub fn settings(&self, name: &str, ancestors: &[String]) -> TableSettings {
+        let mut settings = TableSettings::default();
+        let names = std::iter::once(name).chain(ancestors.iter().map(String::as_str));
+        for (index, name) in names.enumerate() {
+            for section in self.sections.iter() {
+                if section.matcher.is_match(name) {
+                    settings.merge(&section.config, &section.origin, index == 0);
+                }
+            }
+        }
+        settings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::table_config::*;
+
+    fn section(key: &str, config: TableConfig) -> Section {
+        Section {
+            key: key.to_owned(),
+            matcher: Matcher::new(key).unwrap(),
+            config,
+            origin: key.to_owned(),
+        }
+    }
+
+    #[test]
+    fn it_prefers_more_specific_sections() {
+        let configs = TableConfigs {
+            sections: vec![
+                section(
+                    "audit_log",
+                    TableConfig {
+                        limit: Some(10),
+                        ..TableConfig::default()
+                    },
+                ),
+                section(
+                    "audit_*",
+                    TableConfig {
+                        limit: Some(100),
+                        where_clause: Some("true".to_owned()),
+                        ..TableConfig::default()
+                    },
+                ),
+                section(
+                    "/^audit/",
+                    TableConfig {
+                        skip: Some(true),
+                        where_clause: Some("false".to_owned()),
+                        ..TableConfig::default()
+                    },
+                ),
+            ],
+        };
+        let settings = configs.settings("audit_log", &[]);
+        assert_eq!(settings.limit, Some(10));
+        assert_eq!(settings.where_clause.as_deref(), Some("true"));
+        assert_eq!(settings.skip, Some(true));
+        assert_eq!(settings.skip_origin.as_deref(), Some("/^audit/"));
+        assert_eq!(configs.settings("users", &[]).limit, None);
+    }
+
+    #[test]
+    fn it_does_not_apply_parent_queries_to_partitions() {
+        let configs = TableConfigs {
+            sections: vec![section(
+                "events",
+                TableConfig {
+                    query: Some("select 1".to_owned()),
+                    limit: Some(10),
+                    ..TableConfig::default()
+                },
+            )],
+        };
+        let settings = configs.settings("events_2024", &["events".to_owned()]);
+        assert_eq!(settings.query, None);
+        assert_eq!(settings.limit, Some(10));
+    }
+}
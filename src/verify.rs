@@ -0,0 +1,66 @@
+use crate::manifest::Manifest;
+use crate::parcel::Parcel;
+use std::error::Error;
+use std::path::Path;
+
+/// Re-hash every `COPY` block in a parcel and compare it with the manifest,
+/// which is either embedded in the parcel or given as a separate file.
+pub fn verify(file: &Path, manifest: Option<&Path>) -> Result<(), Box<dyn Error>> {
+    let parcel = Parcel::load(file, false)?;
+    let manifest = match manifest {
+        Some(path) => Manifest::load(path)?,
+        None => parcel.manifest.clone().unwrap_or_default(),
+    };
+    if manifest.tables.is_empty() && !parcel.blocks.is_empty() {
+        return Err(
+            "No table manifest found. The parcel may be truncated; pass --manifest to check it against a separate manifest file."
+                .into(),
+        );
+    }
+
+    let mut problems = 0;
+    println!("    Status | Table name");
+    for expected in manifest.tables.iter() {
+        let found = parcel
+            .blocks
+            .iter()
+            .find(|block| block.name == expected.name);
+        let status = match found {
+            None => "missing",
+            Some(block) if !block.complete => "truncated",
+            Some(block) if block.sha256 != expected.sha256 => "mismatch",
+            Some(_) => "ok",
+        };
+        match found {
+            Some(block) if status != "ok" => println!(
+                "{status:>10} | {name} (expected {rows} rows, {bytes} bytes; found {found_rows} rows, {found_bytes} bytes)",
+                name = expected.name,
+                rows = expected.rows,
+                bytes = expected.bytes,
+                found_rows = block.rows,
+                found_bytes = block.bytes,
+            ),
+            _ => println!("{status:>10} | {name}", name = expected.name),
+        }
+        if status != "ok" {
+            problems += 1;
+        }
+    }
+    for block in parcel.blocks.iter() {
+        if !manifest.tables.iter().any(|table| table.name == block.name) {
+            println!(
+                "{status:>10} | {name}",
+                status = "unexpected",
+                name = block.name
+            );
+            problems += 1;
+        }
+    }
+
+    if problems > 0 {
+        Err(format!("{problems} problem(s) found in {}", file.display()).into())
+    } else {
+        println!("Verified {} tables", manifest.tables.len());
+        Ok(())
+    }
+}